@@ -1,10 +1,22 @@
-use chrono::NaiveDateTime;
-use clap::{App, Arg};
-use std::io::Write;
-use vkopt_message_parser::filter::Filter;
-use vkopt_message_parser::reader::{fold_html, EventResult, MessageEvent};
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use clap::{App, Arg, ArgMatches};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use vkopt_message_parser::attachment::AttachmentDetails;
+use vkopt_message_parser::config::FilterConfig;
+use vkopt_message_parser::filter::{
+    parse_date, parse_time_window, BufferedMessage, ContentClass, Filter, ForwardedPolicy,
+    MessageBuffer,
+};
+use vkopt_message_parser::near_duplicates::NearDuplicates;
+use vkopt_message_parser::query::Predicate;
+use vkopt_message_parser::reader::{fold_html, EventResult, MessageAttachmentKind, MessageEvent};
 
 fn main() {
+    let attachment_kinds: Vec<_> = MessageAttachmentKind::ALL.iter().map(|k| k.name()).collect();
+    let content_classes: Vec<_> = ContentClass::ALL.iter().map(|c| c.name()).collect();
     let matches = App::new("VkOpt Message Parser")
         .args(&[
             Arg::with_name("only-include-names")
@@ -12,24 +24,243 @@ fn main() {
                 .help("Filter: screen names (id...) whose messages are included")
                 .multiple(true)
                 .use_delimiter(true)
-                .takes_value(true)
-                .conflicts_with("exclude-names"),
+                .takes_value(true),
             Arg::with_name("exclude-names")
                 .long("exclude-names")
                 .help("Filter: screen names (id...) whose messages are excluded")
                 .multiple(true)
                 .use_delimiter(true)
                 .takes_value(true),
+            Arg::with_name("name-globs")
+                .long("name-globs")
+                .help("Filter: treat * and ? in screen names as wildcards"),
+            Arg::with_name("ignore-name-case")
+                .long("ignore-name-case")
+                .help("Filter: match screen names case-insensitively"),
+            Arg::with_name("only-include-ids")
+                .long("only-include-ids")
+                .help("Filter: numeric ids of users (1 for vk.com/id1) whose messages are included")
+                .validator(|id| id.parse::<i64>().map(|_| ()).map_err(|e| e.to_string()))
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("exclude-ids")
+                .long("exclude-ids")
+                .help("Filter: numeric ids of users whose messages are excluded")
+                .validator(|id| id.parse::<i64>().map(|_| ()).map_err(|e| e.to_string()))
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("mentioning")
+                .long("mentioning")
+                .help("Filter: only include messages mentioning these users (numeric ids or names)")
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
             Arg::with_name("since-date")
                 .long("since-date")
                 .help("Filter: minimum date for a message to be included (ex: 2019.01.01 13:00:00)")
                 .multiple(true)
                 .use_delimiter(true)
                 .takes_value(true),
+            Arg::with_name("until-date")
+                .long("until-date")
+                .help("Filter: maximum date for a message to be included (ex: 2019.12.31 23:59:59)")
+                .takes_value(true),
+            Arg::with_name("weekdays")
+                .long("weekdays")
+                .help("Filter: days of the week on which a message must be sent (ex: sat,sun)")
+                .possible_values(&["mon", "tue", "wed", "thu", "fri", "sat", "sun"])
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("time-of-day")
+                .long("time-of-day")
+                .help("Filter: daily window in which a message must be sent (ex: 09:00-18:00)")
+                .validator(|w| {
+                    parse_time_window(&w)
+                        .map(|_| ())
+                        .ok_or_else(|| format!("{} is not a window such as 09:00-18:00", w))
+                })
+                .takes_value(true),
+            Arg::with_name("timezone")
+                .long("timezone")
+                .help("Timezone for --weekdays and --time-of-day (ex: Asia/Tokyo)")
+                .validator(|tz| tz.parse::<Tz>().map(|_| ()))
+                .requires("dump-timezone")
+                .takes_value(true),
+            Arg::with_name("dump-timezone")
+                .long("dump-timezone")
+                .help("Timezone of the dates in the input files (ex: Europe/Moscow)")
+                .validator(|tz| tz.parse::<Tz>().map(|_| ()))
+                .requires("timezone")
+                .takes_value(true),
+            Arg::with_name("only-include-attachments")
+                .long("only-include-attachments")
+                .help("Filter: attachment kinds, one of which a message must have to be included")
+                .possible_values(&attachment_kinds)
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("exclude-attachments")
+                .long("exclude-attachments")
+                .help("Filter: attachment kinds whose messages are excluded")
+                .possible_values(&attachment_kinds)
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("exclude-attachment-only")
+                .long("exclude-attachment-only")
+                .help("Filter: attachment kinds whose messages are excluded if they have no text")
+                .possible_values(&attachment_kinds)
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("with-attachments")
+                .long("with-attachments")
+                .help("Filter: only include messages with attachments")
+                .conflicts_with("without-attachments"),
+            Arg::with_name("without-attachments")
+                .long("without-attachments")
+                .help("Filter: only include messages without attachments"),
+            Arg::with_name("with-links")
+                .long("with-links")
+                .help("Filter: only include messages with links")
+                .conflicts_with("without-links"),
+            Arg::with_name("without-links")
+                .long("without-links")
+                .help("Filter: only include messages without links"),
+            Arg::with_name("min-length")
+                .long("min-length")
+                .help("Filter: minimum length of a message text (see --length-unit)")
+                .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+            Arg::with_name("max-length")
+                .long("max-length")
+                .help("Filter: maximum length of a message text (see --length-unit)")
+                .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+            Arg::with_name("length-unit")
+                .long("length-unit")
+                .help("Unit of --min-length and --max-length (chars by default)")
+                .possible_values(&["chars", "words"])
+                .takes_value(true),
+            Arg::with_name("only-include-content")
+                .long("only-include-content")
+                .help("Filter: content classes, one of which a message must have to be included")
+                .possible_values(&content_classes)
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("exclude-content")
+                .long("exclude-content")
+                .help("Filter: content classes whose messages are excluded")
+                .possible_values(&content_classes)
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+            Arg::with_name("wordlist")
+                .long("wordlist")
+                .help("Filter: exclude messages containing words from this file (one per line)")
+                .takes_value(true),
+            Arg::with_name("mask-wordlist")
+                .long("mask-wordlist")
+                .help("Filter: replace the words from --wordlist with * instead of excluding"),
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Dedup: drop messages repeating one of the last N messages that pass")
+                .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+            Arg::with_name("dedup-text")
+                .long("dedup-text")
+                .help("Dedup: compare texts exactly or ignoring case, punctuation and whitespace")
+                .possible_values(&["exact", "normalized"])
+                .takes_value(true),
+            Arg::with_name("dedup-per-author")
+                .long("dedup-per-author")
+                .help("Dedup: only drop repeats of the same author's messages"),
+            Arg::with_name("near-duplicates")
+                .long("near-duplicates")
+                .help("Dedup: find messages at least this similar to earlier ones (ex: 0.8)")
+                .validator(|t| match t.parse::<f64>() {
+                    Ok(t) if (0.0..=1.0).contains(&t) => Ok(()),
+                    _ => Err("expected a number from 0 to 1".to_owned()),
+                })
+                .takes_value(true),
+            Arg::with_name("drop-near-duplicates")
+                .long("drop-near-duplicates")
+                .help("Dedup: only include the first message of each cluster of near duplicates"),
+            Arg::with_name("near-duplicate-report")
+                .long("near-duplicate-report")
+                .help("Dedup: write the clusters of near duplicates to this file as JSON")
+                .takes_value(true),
+            Arg::with_name("sample-every")
+                .long("sample-every")
+                .help("Sampling: only include every Nth message that passes the filters")
+                .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .conflicts_with("sample-fraction")
+                .takes_value(true),
+            Arg::with_name("sample-fraction")
+                .long("sample-fraction")
+                .help("Sampling: only include a random fraction of the messages (ex: 0.1)")
+                .validator(|f| match f.parse::<f64>() {
+                    Ok(f) if (0.0..=1.0).contains(&f) => Ok(()),
+                    _ => Err("expected a number from 0 to 1".to_owned()),
+                })
+                .takes_value(true),
+            Arg::with_name("sample-seed")
+                .long("sample-seed")
+                .help("Sampling: seed for --sample-fraction (0 by default)")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .requires("sample-fraction")
+                .takes_value(true),
+            Arg::with_name("forwarded")
+                .long("forwarded")
+                .help(
+                    "Filter: forwarded messages to include: none (default), all, \
+                     or those whose authors pass the name filters",
+                )
+                .possible_values(&ForwardedPolicy::NAMES)
+                .takes_value(true)
+                .conflicts_with("max-forwarded-depth"),
+            Arg::with_name("max-forwarded-depth")
+                .long("max-forwarded-depth")
+                .help("Filter: maximum nesting level of included forwarded messages")
+                .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+            Arg::with_name("where")
+                .long("where")
+                .help(
+                    "Filter: query messages must match \
+                     (ex: author:sota and date>=2019.01.01 and not has:sticker)",
+                )
+                .validator(|q| Predicate::parse(&q).map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+            Arg::with_name("filter-config")
+                .long("filter-config")
+                .help(
+                    "Filter: a TOML (or .json) file with filter settings, \
+                     which are overridden by the options given on the command line",
+                )
+                .takes_value(true),
+            Arg::with_name("format")
+                .long("format")
+                .help(
+                    "Output format: message texts, a GeoJSON collection of shared locations, \
+                     per-user sticker counts, a JSON object per line for each message \
+                     (with forwarded messages nested in it), a CSV table of messages, \
+                     or a SQLite database (appending to an existing one)",
+                )
+                .possible_values(&["text", "geojson", "sticker_stats", "jsonl", "csv", "sqlite"])
+                .default_value("text")
+                .takes_value(true),
             Arg::with_name("text-delimiter")
                 .long("text-delimiter")
                 .help("Delimiter inserted between messages (newline by default)")
                 .takes_value(true),
+            Arg::with_name("voice-message-placeholders")
+                .long("voice-message-placeholders")
+                .help("Insert [voice message] into the text of messages with voice messages"),
             Arg::with_name("output")
                 .short("o")
                 .help("Output file path")
@@ -51,21 +282,198 @@ fn main() {
         .unwrap();
 
     let delimiter = matches.value_of("text-delimiter").unwrap_or("\n");
+    let voice_placeholders = matches.is_present("voice-message-placeholders");
 
-    let short_name_whitelist = matches
-        .values_of("only-include-names")
-        .map(|ns| ns.collect());
-    let short_name_blacklist = matches.values_of("exclude-names").map(|ns| ns.collect());
-    let since_date = matches
-        .value_of("since-date")
-        .map(|d| NaiveDateTime::parse_from_str(d, "%Y.%m.%d %H:%M:%S").unwrap());
-    let filter = Filter {
-        short_name_whitelist,
-        short_name_blacklist,
-        since_date,
+    let mut config = match matches.value_of("filter-config") {
+        Some(path) => FilterConfig::load(path).unwrap_or_else(exit_with_error),
+        None => FilterConfig::default(),
+    };
+    // Options given on the command line override the ones from the config file
+    let value = |arg| matches.value_of(arg).map(str::to_owned);
+    let values = |arg| matches.values_of(arg).map(|vs| vs.map(str::to_owned).collect());
+    let flag = |yes, no| match (matches.is_present(yes), matches.is_present(no)) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
     };
+    config.short_name_whitelist = values("only-include-names").or(config.short_name_whitelist);
+    config.short_name_blacklist = values("exclude-names").or(config.short_name_blacklist);
+    if matches.is_present("name-globs") {
+        config.name_globs = Some(true);
+    }
+    if matches.is_present("ignore-name-case") {
+        config.name_case_insensitive = Some(true);
+    }
+    let ids = |arg| matches.values_of(arg).map(|ids| ids.map(|id| id.parse().unwrap()).collect());
+    config.author_id_whitelist = ids("only-include-ids").or(config.author_id_whitelist);
+    config.author_id_blacklist = ids("exclude-ids").or(config.author_id_blacklist);
+    if let Some(users) = matches.values_of("mentioning") {
+        let (ids, names): (Vec<_>, Vec<_>) = users.partition(|u| u.parse::<i64>().is_ok());
+        let ids = ids.iter().map(|id| id.parse().unwrap()).collect::<Vec<_>>();
+        let names = names.into_iter().map(str::to_owned).collect::<Vec<_>>();
+        config.mention_id_whitelist = Some(ids).filter(|ids| !ids.is_empty());
+        config.mention_name_whitelist = Some(names).filter(|names| !names.is_empty());
+    }
+    config.since_date = value("since-date").or(config.since_date);
+    config.until_date = value("until-date").or(config.until_date);
+    config.weekdays = values("weekdays").or(config.weekdays);
+    config.time_of_day = value("time-of-day").or(config.time_of_day);
+    config.timezone = value("timezone").or(config.timezone);
+    config.dump_timezone = value("dump-timezone").or(config.dump_timezone);
+    config.attachment_kind_whitelist =
+        values("only-include-attachments").or(config.attachment_kind_whitelist);
+    config.attachment_kind_blacklist =
+        values("exclude-attachments").or(config.attachment_kind_blacklist);
+    config.attachment_only_blacklist =
+        values("exclude-attachment-only").or(config.attachment_only_blacklist);
+    config.has_attachments =
+        flag("with-attachments", "without-attachments").or(config.has_attachments);
+    config.has_links = flag("with-links", "without-links").or(config.has_links);
+    config.min_body_length = number(&matches, "min-length").or(config.min_body_length);
+    config.max_body_length = number(&matches, "max-length").or(config.max_body_length);
+    config.body_length_unit = value("length-unit").or(config.body_length_unit);
+    config.content_class_whitelist =
+        values("only-include-content").or(config.content_class_whitelist);
+    config.content_class_blacklist = values("exclude-content").or(config.content_class_blacklist);
+    config.query = value("where").or(config.query);
+    if let Some(forwarded) = value("forwarded") {
+        config.forwarded = Some(forwarded);
+        config.max_forwarded_depth = None;
+    }
+    if let Some(depth) = number(&matches, "max-forwarded-depth") {
+        config.forwarded = None;
+        config.max_forwarded_depth = Some(depth);
+    }
+    if config.forwarded.is_none() && config.max_forwarded_depth.is_none() {
+        config.forwarded = Some("none".to_owned());
+    }
+    config.wordlist = value("wordlist").or(config.wordlist);
+    if matches.is_present("mask-wordlist") {
+        config.mask_wordlist = Some(true);
+    }
+    config.dedup_window = number(&matches, "dedup").or(config.dedup_window);
+    config.dedup_text = value("dedup-text").or(config.dedup_text);
+    if matches.is_present("dedup-per-author") {
+        config.dedup_scope = Some("per_author".to_owned());
+    }
+    config.near_duplicate_threshold =
+        number(&matches, "near-duplicates").or(config.near_duplicate_threshold);
+    if matches.is_present("drop-near-duplicates") {
+        config.drop_near_duplicates = Some(true);
+    }
+    if let Some(n) = number(&matches, "sample-every") {
+        config.sample_every = Some(n);
+        config.sample_fraction = None;
+    }
+    if let Some(fraction) = number(&matches, "sample-fraction") {
+        config.sample_every = None;
+        config.sample_fraction = Some(fraction);
+    }
+    config.sample_seed = number(&matches, "sample-seed").or(config.sample_seed);
+    let filter = config.into_filter().unwrap_or_else(exit_with_error);
+    let report = matches.value_of("near-duplicate-report").map(|report| {
+        let near_duplicates = filter.near_duplicates.as_ref().unwrap_or_else(|| {
+            exit_with_error("--near-duplicate-report requires a near duplicate threshold")
+        });
+        (report, near_duplicates)
+    });
+
+    match matches.value_of("format").unwrap() {
+        "geojson" => write_geojson(inputs, output, &filter).unwrap(),
+        "sticker_stats" => write_sticker_stats(inputs, output, &filter).unwrap(),
+        "jsonl" => write_jsonl(inputs, output, &filter).unwrap(),
+        "csv" => write_csv(inputs, output, &filter).unwrap(),
+        "sqlite" => write_sqlite(inputs, output, &filter).unwrap_or_else(exit_with_error),
+        _ => write(inputs, output, &filter, delimiter, voice_placeholders).unwrap(),
+    }
+    if let Some((report, near_duplicates)) = report {
+        write_near_duplicate_report(report, near_duplicates).unwrap();
+    }
+}
+
+// Parses an option that has been checked by a validator
+fn number<T: std::str::FromStr>(matches: &ArgMatches, arg: &str) -> Option<T>
+where
+    T::Err: std::fmt::Debug,
+{
+    matches.value_of(arg).map(|n| n.parse().unwrap())
+}
 
-    write(inputs, output, &filter, delimiter).unwrap();
+fn exit_with_error<E: std::fmt::Display, T>(error: E) -> T {
+    eprintln!("{}", error);
+    std::process::exit(1)
+}
+
+// Where a message is in the dump, regardless of the messages rejected by the filter
+#[derive(Debug, Default)]
+struct DumpPosition {
+    // Indices of the input and of the top-level message in it; ids in the dump may repeat
+    input: usize,
+    message: usize,
+    msg_id: Option<u64>,
+    // Positions of the message and the messages it is forwarded in, by level
+    positions: Vec<u32>,
+    forwarded: u32,
+}
+
+impl DumpPosition {
+    fn start(&mut self, level: u32) {
+        if level == 0 {
+            let message = if self.positions.is_empty() { 0 } else { self.message + 1 };
+            let input = self.input;
+            *self = DumpPosition { input, message, positions: vec![0], ..Default::default() };
+        } else {
+            self.forwarded += 1;
+            self.positions.truncate(level as usize);
+            self.positions.push(self.forwarded);
+        }
+    }
+
+    // 0 for top-level messages, then forwarded messages in the order of the dump
+    fn position(&self) -> u32 {
+        self.positions.last().copied().unwrap_or(0)
+    }
+
+    fn parent_position(&self) -> Option<u32> {
+        self.positions.len().checked_sub(2).map(|i| self.positions[i])
+    }
+}
+
+// Folds messages that pass the filter, along with their positions in the dump
+fn fold_messages<'w, A, F>(
+    inputs: &[&'w str],
+    filter: &Filter<'w>,
+    init: A,
+    mut f: F,
+) -> quick_xml::Result<A>
+where
+    F: FnMut(A, &BufferedMessage, &DumpPosition) -> A,
+{
+    let mut acc = init;
+    for (input, i) in inputs.iter().enumerate() {
+        let mut buffer = MessageBuffer::new(filter);
+        let mut position = DumpPosition { input, ..Default::default() };
+        acc = fold_html(i, acc, |acc, event| {
+            let started = match event {
+                MessageEvent::Start(level) => Some(level),
+                MessageEvent::MessageIdExtracted(id) => {
+                    position.msg_id = Some(id);
+                    None
+                }
+                _ => None,
+            };
+            let acc = match buffer.push(event) {
+                Some(message) => f(acc, &message, &position),
+                None => acc,
+            };
+            // The buffer completes a message when the next one starts
+            if let Some(level) = started {
+                position.start(level);
+            }
+            EventResult::Consumed(acc)
+        })?;
+    }
+    Ok(acc)
 }
 
 fn write<'w>(
@@ -73,38 +481,462 @@ fn write<'w>(
     output: &'w str,
     filter: &Filter<'w>,
     delimiter: &'w str,
+    voice_placeholders: bool,
 ) -> quick_xml::Result<()> {
-    let folded: quick_xml::Result<Vec<String>> = inputs
-        .iter()
-        .map(|i| {
-            let mut message_empty = true;
-            fold_html(i, String::new(), |mut acc, event| {
-                match filter.filter_event(event) {
-                    Some(e) => match e {
-                        MessageEvent::Start(0) => {
-                            if !message_empty {
-                                acc += delimiter;
-                                message_empty = true;
-                            }
-                            EventResult::Consumed(acc)
-                        }
-                        MessageEvent::Start(_) => EventResult::SkipMessage(acc),
-                        MessageEvent::BodyPartExtracted(body) if !body.is_empty() => {
-                            acc += body;
-                            message_empty = false;
-                            EventResult::Consumed(acc)
-                        }
-                        _ => EventResult::Consumed(acc),
-                    },
-                    None => EventResult::SkipMessage(acc),
+    let texts = fold_messages(&inputs, filter, Vec::new(), |mut acc, message, _| {
+        let mut text = message.body();
+        if voice_placeholders {
+            for kind in message.attachment_kinds() {
+                if kind == MessageAttachmentKind::AudioMessage {
+                    if !text.is_empty() {
+                        text += " ";
+                    }
+                    text += "[voice message]";
                 }
-            })
+            }
+        }
+        if !text.is_empty() {
+            acc.push(text);
+        }
+        acc
+    })?;
+
+    let mut out = std::fs::File::create(output)?;
+    write!(&mut out, "{}", texts.join(delimiter))?;
+    Ok(())
+}
+
+fn write_jsonl<'w>(
+    inputs: Vec<&'w str>,
+    output: &'w str,
+    filter: &Filter<'w>,
+) -> quick_xml::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    // Messages whose forwarded messages may still follow
+    let init: std::io::Result<Vec<JsonlMessage>> = Ok(Vec::new());
+    let stack = fold_messages(&inputs, filter, init, |stack, message, position| {
+        let mut stack = stack?;
+        let ancestors = &position.positions[..position.positions.len() - 1];
+        while stack.last().is_some_and(|m| {
+            (m.input, m.message) != (position.input, position.message)
+                || !ancestors.contains(&m.position)
+        }) {
+            pop_jsonl_message(&mut stack, &mut out)?;
+        }
+        let nested = stack.last().is_some_and(|m| Some(m.position) == position.parent_position());
+        stack.push(JsonlMessage {
+            input: position.input,
+            message: position.message,
+            position: position.position(),
+            nested,
+            json: message_json(message, filter),
+        });
+        Ok(stack)
+    })?;
+    let mut stack = stack?;
+    while !stack.is_empty() {
+        pop_jsonl_message(&mut stack, &mut out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+struct JsonlMessage {
+    input: usize,
+    message: usize,
+    position: u32,
+    // Forwarded in the message below it on the stack
+    nested: bool,
+    json: Value,
+}
+
+// Adds the last message to the forwarded messages of its parent, or writes it if the parent was
+// rejected by the filter
+fn pop_jsonl_message<W: Write>(
+    stack: &mut Vec<JsonlMessage>,
+    out: &mut W,
+) -> std::io::Result<()> {
+    let message = stack.pop().unwrap();
+    if message.nested {
+        let parent = &mut stack.last_mut().unwrap().json;
+        parent["forwarded"].as_array_mut().unwrap().push(message.json);
+    } else {
+        serde_json::to_writer(&mut *out, &message.json)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn message_json(message: &BufferedMessage, filter: &Filter) -> Value {
+    let mut mentions = Vec::new();
+    let mut attachments = Vec::new();
+    for e in message.events.iter() {
+        match e.as_event() {
+            MessageEvent::MentionExtracted { id, name } => {
+                mentions.push(json!({ "id": id, "name": name }));
+            }
+            MessageEvent::AttachmentExtracted { kind, url, vk_obj, description } => {
+                attachments.push(json!({
+                    "kind": kind.name(),
+                    "url": url,
+                    "vk_obj": vk_obj,
+                    "description": description
+                }));
+            }
+            _ => {}
+        }
+    }
+    json!({
+        "id": message.id(),
+        "author": {
+            "id": message.author_id(),
+            "short_name": message.short_name(),
+            "full_name": message.full_name()
+        },
+        "date": message.date().and_then(|date| iso_date(date, filter)),
+        "level": message.level,
+        "body": message.body(),
+        "mentions": mentions,
+        "attachments": attachments,
+        "forwarded": []
+    })
+}
+
+// With --dump-timezone, dates include the offset
+fn iso_date(date: &str, filter: &Filter) -> Option<String> {
+    let date = parse_date(date).ok()?;
+    match filter.dump_timezone {
+        Some(tz) => tz.from_local_datetime(&date).earliest().map(|d| d.to_rfc3339()),
+        None => Some(date.format("%Y-%m-%dT%H:%M:%S").to_string()),
+    }
+}
+
+fn write_csv<'w>(
+    inputs: Vec<&'w str>,
+    output: &'w str,
+    filter: &Filter<'w>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut out = std::fs::File::create(output)?;
+    // The byte order mark makes spreadsheet applications read the file as UTF-8
+    out.write_all("\u{FEFF}".as_bytes())?;
+    let mut csv = csv::Writer::from_writer(out);
+    csv.write_record([
+        "date",
+        "author_id",
+        "short_name",
+        "full_name",
+        "body",
+        "attachment_count",
+        "attachment_kinds",
+        "level",
+    ])?;
+    fold_messages(&inputs, filter, Ok(()), |result: csv::Result<()>, message, _| {
+        result?;
+        let kinds = message.attachment_kinds();
+        let kind_names: Vec<_> = kinds.iter().map(|k| k.name()).collect();
+        csv.write_record([
+            // Spreadsheet applications recognize dates without the T separator
+            message
+                .date()
+                .and_then(|date| parse_date(date).ok())
+                .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            message.author_id().map(|id| id.to_string()).unwrap_or_default(),
+            spreadsheet_text(message.short_name().unwrap_or("")),
+            spreadsheet_text(message.full_name().unwrap_or("")),
+            spreadsheet_text(&message.body()),
+            kinds.len().to_string(),
+            kind_names.join(" "),
+            message.level.to_string(),
+        ])
+    })??;
+    csv.flush()?;
+    Ok(())
+}
+
+// Spreadsheet applications evaluate cells starting with these as formulas, so they are prefixed
+// with an apostrophe to be read as text
+fn spreadsheet_text(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", text)
+    } else {
+        text.to_owned()
+    }
+}
+
+// Messages are identified by their places in the dump, so importing a dump again updates the
+// same rows whatever the filters, while other dumps never overwrite them
+const SQLITE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY,
+        vk_id INTEGER UNIQUE,
+        short_name TEXT NOT NULL,
+        full_name TEXT NOT NULL
+    );
+    -- Users without a numeric id are told apart by their short names
+    CREATE UNIQUE INDEX IF NOT EXISTS users_short_name ON users (short_name) WHERE vk_id IS NULL;
+    CREATE TABLE IF NOT EXISTS dumps (
+        id INTEGER PRIMARY KEY,
+        -- FNV-1a of the file, so that edited copies of a dump are told apart
+        hash INTEGER NOT NULL UNIQUE,
+        -- The path the dump was last imported from
+        path TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        dump_id INTEGER NOT NULL REFERENCES dumps (id),
+        -- The index of the top-level message in the dump; forwarded messages share the index
+        -- of the message they are forwarded in
+        dump_message INTEGER NOT NULL,
+        -- The id of the top-level message in the dump (msg1), which may be missing or repeated
+        msg_id INTEGER,
+        -- 0 for the message itself, then the messages forwarded in it in the order of the dump
+        position INTEGER NOT NULL,
+        -- The position of the message this one is forwarded in
+        parent_position INTEGER,
+        user_id INTEGER NOT NULL REFERENCES users (id),
+        date TEXT NOT NULL,
+        body TEXT NOT NULL,
+        UNIQUE (dump_id, dump_message, position)
+    );
+    CREATE INDEX IF NOT EXISTS messages_date ON messages (date);
+    CREATE INDEX IF NOT EXISTS messages_user_id ON messages (user_id);
+    CREATE TABLE IF NOT EXISTS attachments (
+        message_id INTEGER NOT NULL REFERENCES messages (id),
+        position INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        url TEXT NOT NULL,
+        vk_obj TEXT NOT NULL,
+        description TEXT NOT NULL,
+        -- Attached to a wall post shared in the message
+        in_wall_post INTEGER NOT NULL,
+        PRIMARY KEY (message_id, position)
+    );
+    CREATE VIEW IF NOT EXISTS forwards AS
+        SELECT parent.id AS message_id, forwarded.id AS forwarded_id
+        FROM messages forwarded
+        JOIN messages parent
+        ON parent.dump_id = forwarded.dump_id
+        AND parent.dump_message = forwarded.dump_message
+        AND parent.position = forwarded.parent_position;
+";
+
+fn write_sqlite<'w>(
+    inputs: Vec<&'w str>,
+    output: &'w str,
+    filter: &Filter<'w>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut db = rusqlite::Connection::open(output)?;
+    db.execute_batch(SQLITE_SCHEMA)?;
+    let tx = db.transaction()?;
+    let mut dumps = Vec::new();
+    for input in inputs.iter() {
+        let id: i64 = tx
+            .prepare_cached(
+                "INSERT INTO dumps (hash, path) VALUES (?1, ?2)
+                 ON CONFLICT (hash) DO UPDATE SET path = excluded.path
+                 RETURNING id",
+            )?
+            .query_row(rusqlite::params![dump_hash(input)?, input], |row| row.get(0))?;
+        dumps.push(id);
+    }
+    let init: Result<(), Box<dyn std::error::Error>> = Ok(());
+    fold_messages(&inputs, filter, init, |result, message, position| {
+        result.and_then(|()| insert_sqlite_message(&tx, message, dumps[position.input], position))
+    })??;
+    tx.commit()?;
+    Ok(())
+}
+
+// SQLite integers are signed, so the hash is stored as i64
+fn dump_hash(path: &str) -> std::io::Result<i64> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    loop {
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(hash as i64);
+        }
+        for byte in buf {
+            hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        let len = buf.len();
+        file.consume(len);
+    }
+}
+
+fn insert_sqlite_message(
+    tx: &rusqlite::Transaction,
+    message: &BufferedMessage,
+    dump_id: i64,
+    position: &DumpPosition,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let user = rusqlite::params![
+        message.author_id(),
+        message.short_name().unwrap_or(""),
+        message.full_name().unwrap_or("")
+    ];
+    let user_id: i64 = match message.author_id() {
+        Some(_) => tx.prepare_cached(
+            "INSERT INTO users (vk_id, short_name, full_name) VALUES (?1, ?2, ?3)
+             ON CONFLICT (vk_id) DO UPDATE
+             SET short_name = excluded.short_name, full_name = excluded.full_name
+             RETURNING id",
+        ),
+        None => tx.prepare_cached(
+            "INSERT INTO users (vk_id, short_name, full_name) VALUES (?1, ?2, ?3)
+             ON CONFLICT (short_name) WHERE vk_id IS NULL DO UPDATE
+             SET full_name = excluded.full_name
+             RETURNING id",
+        ),
+    }?
+    .query_row(user, |row| row.get(0))?;
+
+    let msg_id = position.msg_id;
+    let dump_message = position.message;
+    let (position, parent_position) = (position.position(), position.parent_position());
+    let date = message
+        .date()
+        .and_then(|date| parse_date(date).ok())
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default();
+    // Importing the message again updates its body, e.g. when it is masked differently
+    let id: i64 = tx
+        .prepare_cached(
+            "INSERT INTO messages
+             (dump_id, dump_message, msg_id, position, parent_position, user_id, date, body)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (dump_id, dump_message, position) DO UPDATE
+             SET body = excluded.body
+             RETURNING id",
+        )?
+        .query_row(
+            rusqlite::params![
+                dump_id,
+                dump_message,
+                msg_id,
+                position,
+                parent_position,
+                user_id,
+                date,
+                message.body()
+            ],
+            |row| row.get(0),
+        )?;
+
+    tx.prepare_cached("DELETE FROM attachments WHERE message_id = ?1")?.execute([id])?;
+    let mut insert_attachment =
+        tx.prepare_cached("INSERT INTO attachments VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+    let mut attachments = 0;
+    let mut in_wall_post = false;
+    for e in message.events.iter() {
+        match e.as_event() {
+            MessageEvent::WallAttachmentsStart => in_wall_post = true,
+            MessageEvent::WallAttachmentsEnd => in_wall_post = false,
+            MessageEvent::AttachmentExtracted { kind, url, vk_obj, description } => {
+                insert_attachment.execute(rusqlite::params![
+                    id,
+                    attachments,
+                    kind.name(),
+                    url,
+                    vk_obj,
+                    description,
+                    in_wall_post
+                ])?;
+                attachments += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_geojson<'w>(
+    inputs: Vec<&'w str>,
+    output: &'w str,
+    filter: &Filter<'w>,
+) -> quick_xml::Result<()> {
+    let features = fold_messages(&inputs, filter, Vec::new(), |mut acc, message, _| {
+        for e in message.events.iter() {
+            if let Some(AttachmentDetails::Location { place, coordinates: Some(c) }) =
+                e.as_event().attachment_details()
+            {
+                acc.push(json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [c.longitude, c.latitude]
+                    },
+                    "properties": {
+                        "place": place,
+                        "short_name": message.short_name(),
+                        "date": message.date()
+                    }
+                }));
+            }
+        }
+        acc
+    })?;
+
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    let out = std::fs::File::create(output)?;
+    serde_json::to_writer_pretty(out, &collection).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+fn write_near_duplicate_report(
+    output: &str,
+    near_duplicates: &NearDuplicates,
+) -> std::io::Result<()> {
+    let clusters: Vec<_> = near_duplicates
+        .clusters()
+        .into_iter()
+        .map(|members| {
+            let members: Vec<_> = members
+                .into_iter()
+                .map(|m| json!({ "short_name": m.short_name, "date": m.date, "body": m.body }))
+                .collect();
+            json!({ "size": members.len(), "messages": members })
         })
         .collect();
+    let out = std::fs::File::create(output)?;
+    serde_json::to_writer_pretty(out, &clusters).map_err(std::io::Error::from)
+}
+
+fn write_sticker_stats<'w>(
+    inputs: Vec<&'w str>,
+    output: &'w str,
+    filter: &Filter<'w>,
+) -> quick_xml::Result<()> {
+    let init: BTreeMap<String, BTreeMap<u32, usize>> = BTreeMap::new();
+    let stats = fold_messages(&inputs, filter, init, |mut acc, message, _| {
+        // Stickers of shared wall posts were not sent by the author of the message
+        let mut in_wall = false;
+        for e in message.events.iter() {
+            match e.as_event() {
+                MessageEvent::WallAttachmentsStart => in_wall = true,
+                MessageEvent::WallAttachmentsEnd => in_wall = false,
+                event if !in_wall => {
+                    if let Some(AttachmentDetails::Sticker { id, .. }) = event.attachment_details()
+                    {
+                        let short_name = message.short_name().unwrap_or("").to_owned();
+                        *acc.entry(short_name).or_default().entry(id).or_insert(0) += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        acc
+    })?;
 
     let mut out = std::fs::File::create(output)?;
-    for acc in folded?.iter() {
-        write!(&mut out, "{}", acc)?;
+    writeln!(&mut out, "short_name\tsticker_id\tcount")?;
+    for (short_name, user_stats) in stats.iter() {
+        let mut counts: Vec<_> = user_stats.iter().collect();
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (sticker_id, count) in counts {
+            writeln!(&mut out, "{}\t{}\t{}", short_name, sticker_id, count)?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,216 @@
+use crate::reader::MessageAttachmentKind;
+use regex::Regex;
+use std::fmt;
+use std::time::Duration;
+
+lazy_static! {
+    static ref PHOTO_SIZE_RE: Regex = Regex::new(r"^\((?P<w>\d+)x(?P<h>\d+)\)$").unwrap();
+    static ref MEDIA_DURATION_RE: Regex =
+        Regex::new(r"^(?P<title>.*?)\s*\((?:(?P<h>\d+):)?(?P<m>\d+):(?P<s>\d{2})\)$").unwrap();
+    static ref STICKER_ID_RE: Regex = Regex::new(r"^Sticker #(?P<id>\d+)$").unwrap();
+    static ref STICKER_URL_RE: Regex =
+        Regex::new(r"/sticker/(?P<product>\d+)-(?P<id>\d+)(?:-(?P<size>\d+)b?)?$").unwrap();
+    static ref MAPS_QUERY_RE: Regex = Regex::new(
+        r"[?&]q=(?P<lat>-?\d+(?:\.\d+)?)(?:\s|%20|\+|,)+(?P<lon>-?\d+(?:\.\d+)?)"
+    )
+    .unwrap();
+    static ref VK_OBJECT_RE: Regex = Regex::new(
+        r"^(?P<kind>[a-z]+)(?P<owner>-?\d+)_(?P<item>\d+)(?:_(?P<key>[0-9A-Za-z]+))?$"
+    )
+    .unwrap();
+}
+
+/// Typed contents of the free-text `description` (and, for locations and stickers, the `url`)
+/// of an `AttachmentExtracted` event.
+#[derive(Debug, PartialEq, Clone)]
+pub enum AttachmentDetails<'a> {
+    Doc {
+        name: &'a str,
+    },
+    Photo {
+        width: u32,
+        height: u32,
+    },
+    Video {
+        title: &'a str,
+        duration: Option<Duration>,
+    },
+    Audio {
+        artist: Option<&'a str>,
+        title: &'a str,
+        duration: Option<Duration>,
+    },
+    AudioMessage {
+        name: &'a str,
+    },
+    Sticker {
+        id: u32,
+        product_id: Option<u32>,
+        size: Option<u32>,
+    },
+    Location {
+        place: &'a str,
+        coordinates: Option<Coordinates>,
+    },
+    Wall,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Location attachments link to Google Maps with the coordinates in the query:
+    /// `https://maps.google.ru/maps?q=34.69 135.50`
+    pub fn from_maps_url(url: &str) -> Option<Self> {
+        let caps = MAPS_QUERY_RE.captures(url)?;
+        Some(Coordinates {
+            latitude: caps["lat"].parse().ok()?,
+            longitude: caps["lon"].parse().ok()?,
+        })
+    }
+}
+
+impl<'a> AttachmentDetails<'a> {
+    /// Returns `None` if the description does not follow the format VkOpt uses for `kind`.
+    pub fn parse(kind: MessageAttachmentKind, url: &str, description: &'a str) -> Option<Self> {
+        match kind {
+            MessageAttachmentKind::Doc => Some(AttachmentDetails::Doc { name: description }),
+            MessageAttachmentKind::Photo => {
+                let caps = PHOTO_SIZE_RE.captures(description)?;
+                Some(AttachmentDetails::Photo {
+                    width: caps["w"].parse().ok()?,
+                    height: caps["h"].parse().ok()?,
+                })
+            }
+            MessageAttachmentKind::Video => {
+                let (title, duration) = split_duration(description);
+                Some(AttachmentDetails::Video { title, duration })
+            }
+            MessageAttachmentKind::Audio => {
+                let (full_title, duration) = split_duration(description);
+                let mut title_split = full_title.splitn(2, " - ");
+                let (artist, title) = match (title_split.next(), title_split.next()) {
+                    (Some(artist), Some(title)) => (Some(artist), title),
+                    _ => (None, full_title),
+                };
+                Some(AttachmentDetails::Audio { artist, title, duration })
+            }
+            MessageAttachmentKind::AudioMessage => {
+                Some(AttachmentDetails::AudioMessage { name: description })
+            }
+            MessageAttachmentKind::Sticker => {
+                // Sticker urls look like https://vk.com/sticker/1-162-256b (product, sticker, size)
+                let url_caps = STICKER_URL_RE.captures(url);
+                let url_component = |name| {
+                    let caps = url_caps.as_ref()?;
+                    caps.name(name)?.as_str().parse::<u32>().ok()
+                };
+                let id = match STICKER_ID_RE.captures(description) {
+                    Some(caps) => caps["id"].parse().ok(),
+                    None => url_component("id"),
+                }?;
+                Some(AttachmentDetails::Sticker {
+                    id,
+                    product_id: url_component("product"),
+                    size: url_component("size"),
+                })
+            }
+            MessageAttachmentKind::Location => {
+                let place = description.strip_prefix("Location:")?.trim();
+                let coordinates = Coordinates::from_maps_url(url);
+                Some(AttachmentDetails::Location { place, coordinates })
+            }
+            MessageAttachmentKind::Wall => Some(AttachmentDetails::Wall),
+        }
+    }
+}
+
+// Media descriptions end with the duration in parentheses: "Title (3:33)", "Title (1:02:03)"
+fn split_duration(description: &str) -> (&str, Option<Duration>) {
+    let caps = match MEDIA_DURATION_RE.captures(description) {
+        Some(caps) => caps,
+        None => return (description, None),
+    };
+    let component = |name| caps.name(name).map_or(Some(0), |m| m.as_str().parse::<u64>().ok());
+    match (component("h"), component("m"), component("s")) {
+        (Some(h), Some(m), Some(s)) => {
+            let title = caps.name("title").unwrap().as_str();
+            (title, Some(Duration::from_secs(h * 3600 + m * 60 + s)))
+        }
+        _ => (description, None),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum VkObjectKind {
+    Photo,
+    Video,
+    Audio,
+    Doc,
+    Wall,
+    Poll,
+}
+
+impl VkObjectKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            VkObjectKind::Photo => "photo",
+            VkObjectKind::Video => "video",
+            VkObjectKind::Audio => "audio",
+            VkObjectKind::Doc => "doc",
+            VkObjectKind::Wall => "wall",
+            VkObjectKind::Poll => "poll",
+        }
+    }
+}
+
+/// A reference to a VK object as found in the `vk_obj` field of attachments,
+/// e.g. `photo1_2` or `wall-1_2`. The owner id is negative for objects owned by communities.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct VkObjectRef {
+    pub kind: VkObjectKind,
+    pub owner_id: i64,
+    pub item_id: u64,
+    pub access_key: Option<String>,
+}
+
+impl VkObjectRef {
+    /// Returns `None` for unknown object types and malformed references
+    /// (some dumps contain references without an owner id, e.g. `video_00000`).
+    pub fn parse(vk_obj: &str) -> Option<Self> {
+        let caps = VK_OBJECT_RE.captures(vk_obj)?;
+        let kind = match &caps["kind"] {
+            "photo" => VkObjectKind::Photo,
+            "video" => VkObjectKind::Video,
+            "audio" => VkObjectKind::Audio,
+            "doc" => VkObjectKind::Doc,
+            "wall" => VkObjectKind::Wall,
+            "poll" => VkObjectKind::Poll,
+            _ => return None,
+        };
+        Some(VkObjectRef {
+            kind,
+            owner_id: caps["owner"].parse().ok()?,
+            item_id: caps["item"].parse().ok()?,
+            access_key: caps.name("key").map(|k| k.as_str().to_owned()),
+        })
+    }
+
+    /// Access keys differ between shares of the same object, so this is the key to deduplicate by.
+    pub fn without_access_key(&self) -> Self {
+        VkObjectRef { access_key: None, ..self.clone() }
+    }
+}
+
+impl fmt::Display for VkObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}_{}", self.kind.as_str(), self.owner_id, self.item_id)?;
+        if let Some(ref access_key) = self.access_key {
+            write!(f, "_{}", access_key)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use crate::filter::{
+    parse_date, parse_time_window, ContentClass, Dedup, DedupScope, DedupText, Filter,
+    ForwardedPolicy, LengthUnit, Sample,
+};
+use crate::near_duplicates::NearDuplicates;
+use crate::query::Predicate;
+use crate::wordlist::Wordlist;
+use crate::reader::MessageAttachmentKind;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+/// Filter settings as stored in a TOML or JSON file, e.g.
+///
+/// ```toml
+/// forwarded = "matching_authors"
+/// author_id_whitelist = [1, 2]
+/// since_date = "2019.01.01 00:00:00"
+/// content_class_blacklist = ["emoji_only", "bot_command"]
+/// query = "not has:sticker"
+/// ```
+///
+/// Keys are named after the `Filter` fields; values use the same names as the CLI options.
+#[derive(Debug, Default, PartialEq, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// `none`, `all` or `matching_authors`
+    pub forwarded: Option<String>,
+    pub max_forwarded_depth: Option<u32>,
+    pub since_date: Option<String>,
+    pub until_date: Option<String>,
+    pub short_name_whitelist: Option<Vec<String>>,
+    pub short_name_blacklist: Option<Vec<String>>,
+    pub full_name_whitelist: Option<Vec<String>>,
+    pub full_name_blacklist: Option<Vec<String>>,
+    pub name_globs: Option<bool>,
+    pub name_case_insensitive: Option<bool>,
+    pub author_id_whitelist: Option<Vec<i64>>,
+    pub author_id_blacklist: Option<Vec<i64>>,
+    pub mention_id_whitelist: Option<Vec<i64>>,
+    pub mention_name_whitelist: Option<Vec<String>>,
+    pub body_whitelist: Option<String>,
+    pub body_blacklist: Option<String>,
+    pub has_attachments: Option<bool>,
+    pub attachment_kind_whitelist: Option<Vec<String>>,
+    pub attachment_kind_blacklist: Option<Vec<String>>,
+    pub attachment_only_blacklist: Option<Vec<String>>,
+    pub has_links: Option<bool>,
+    pub min_body_length: Option<usize>,
+    pub max_body_length: Option<usize>,
+    /// `chars` or `words`
+    pub body_length_unit: Option<String>,
+    pub content_class_whitelist: Option<Vec<String>>,
+    pub content_class_blacklist: Option<Vec<String>>,
+    pub weekdays: Option<Vec<String>>,
+    /// A daily window such as `09:00-18:00`
+    pub time_of_day: Option<String>,
+    pub dump_timezone: Option<String>,
+    pub timezone: Option<String>,
+    /// The path of a `Wordlist` file
+    pub wordlist: Option<String>,
+    pub mask_wordlist: Option<bool>,
+    pub query: Option<String>,
+    pub dedup_window: Option<usize>,
+    /// `exact` or `normalized`
+    pub dedup_text: Option<String>,
+    /// `global` or `per_author`
+    pub dedup_scope: Option<String>,
+    /// From 0 to 1, see `NearDuplicates`
+    pub near_duplicate_threshold: Option<f64>,
+    pub drop_near_duplicates: Option<bool>,
+    pub sample_every: Option<u64>,
+    pub sample_fraction: Option<f64>,
+    pub sample_seed: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    InvalidValue { field: &'static str, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "cannot read the filter config: {}", e),
+            ConfigError::Toml(e) => write!(f, "invalid filter config: {}", e),
+            ConfigError::Json(e) => write!(f, "invalid filter config: {}", e),
+            ConfigError::InvalidValue { field, message } => {
+                write!(f, "invalid value of {}: {}", field, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Json(e)
+    }
+}
+
+fn invalid(field: &'static str, message: impl ToString) -> ConfigError {
+    ConfigError::InvalidValue { field, message: message.to_string() }
+}
+
+impl FilterConfig {
+    /// Reads a `.json` file as JSON and any other file as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            _ => Self::from_toml(&contents),
+        }
+    }
+
+    pub fn from_toml(config: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    pub fn from_json(config: &str) -> Result<Self, ConfigError> {
+        Ok(serde_json::from_str(config)?)
+    }
+
+    /// Builds an owned `Filter`, validating the values.
+    pub fn into_filter(self) -> Result<Filter<'static>, ConfigError> {
+        let forwarded = match (self.forwarded.as_deref(), self.max_forwarded_depth) {
+            (None, None) => ForwardedPolicy::Include,
+            (None, Some(depth)) => ForwardedPolicy::MaxDepth(depth),
+            (Some(_), Some(_)) => {
+                return Err(invalid("max_forwarded_depth", "conflicts with forwarded"));
+            }
+            (Some(name), None) => {
+                ForwardedPolicy::from_name(name).ok_or_else(|| invalid("forwarded", name))?
+            }
+        };
+        let date = |field, date: Option<String>| {
+            date.map(|d| parse_date(&d).map_err(|e| invalid(field, format!("{}: {}", d, e))))
+                .transpose()
+        };
+        let regex = |field, regex: Option<String>| {
+            regex.map(|r| Regex::new(&r).map_err(|e| invalid(field, e))).transpose()
+        };
+        let timezone = |field, tz: Option<String>| {
+            tz.map(|tz| tz.parse::<Tz>().map_err(|e| invalid(field, e))).transpose()
+        };
+        match (&self.dump_timezone, &self.timezone) {
+            (Some(_), None) => return Err(invalid("timezone", "is required by dump_timezone")),
+            (None, Some(_)) => return Err(invalid("dump_timezone", "is required by timezone")),
+            _ => {}
+        }
+        let sample = match (self.sample_every, self.sample_fraction) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("sample_every", "conflicts with sample_fraction"));
+            }
+            (Some(n), None) => Some(Sample::every_nth(n)),
+            (None, Some(fraction)) if !(0.0..=1.0).contains(&fraction) => {
+                return Err(invalid("sample_fraction", fraction));
+            }
+            (None, Some(fraction)) => {
+                Some(Sample::fraction(fraction, self.sample_seed.unwrap_or(0)))
+            }
+            (None, None) => None,
+        };
+        let dedup_text = match self.dedup_text.as_deref() {
+            None | Some("exact") => DedupText::Exact,
+            Some("normalized") => DedupText::Normalized,
+            Some(text) => return Err(invalid("dedup_text", text)),
+        };
+        let dedup_scope = match self.dedup_scope.as_deref() {
+            None | Some("global") => DedupScope::Global,
+            Some("per_author") => DedupScope::PerAuthor,
+            Some(scope) => return Err(invalid("dedup_scope", scope)),
+        };
+        let dedup = match self.dedup_window {
+            Some(window) => Some(Dedup::new(window, dedup_text, dedup_scope)),
+            None if self.dedup_text.is_some() || self.dedup_scope.is_some() => {
+                return Err(invalid("dedup_window", "is required by dedup_text and dedup_scope"));
+            }
+            None => None,
+        };
+        let near_duplicates = match self.near_duplicate_threshold {
+            Some(threshold) if !(0.0..=1.0).contains(&threshold) => {
+                return Err(invalid("near_duplicate_threshold", threshold));
+            }
+            Some(threshold) => Some(NearDuplicates::new(
+                threshold,
+                self.drop_near_duplicates.unwrap_or(false),
+            )),
+            None if self.drop_near_duplicates.is_some() => {
+                let message = "is required by drop_near_duplicates";
+                return Err(invalid("near_duplicate_threshold", message));
+            }
+            None => None,
+        };
+        let wordlist = match self.wordlist {
+            Some(path) => Some(
+                Wordlist::load(&path).map_err(|e| invalid("wordlist", format!("{}: {}", path, e)))?,
+            ),
+            None if self.mask_wordlist.is_some() => {
+                return Err(invalid("wordlist", "is required by mask_wordlist"));
+            }
+            None => None,
+        };
+        Ok(Filter {
+            forwarded,
+            since_date: date("since_date", self.since_date)?,
+            until_date: date("until_date", self.until_date)?,
+            date_range: None,
+            short_name_whitelist: self.short_name_whitelist.map(owned_names),
+            short_name_blacklist: self.short_name_blacklist.map(owned_names),
+            full_name_whitelist: self.full_name_whitelist.map(owned_names),
+            full_name_blacklist: self.full_name_blacklist.map(owned_names),
+            name_globs: self.name_globs.unwrap_or(false),
+            name_case_insensitive: self.name_case_insensitive.unwrap_or(false),
+            author_id_whitelist: self.author_id_whitelist.map(|ids| ids.into_iter().collect()),
+            author_id_blacklist: self.author_id_blacklist.map(|ids| ids.into_iter().collect()),
+            mention_id_whitelist: self.mention_id_whitelist.map(|ids| ids.into_iter().collect()),
+            mention_name_whitelist: self.mention_name_whitelist.map(owned_names),
+            body_whitelist: regex("body_whitelist", self.body_whitelist)?,
+            body_blacklist: regex("body_blacklist", self.body_blacklist)?,
+            has_attachments: self.has_attachments,
+            attachment_kind_whitelist: attachment_kinds(
+                "attachment_kind_whitelist",
+                self.attachment_kind_whitelist,
+            )?,
+            attachment_kind_blacklist: attachment_kinds(
+                "attachment_kind_blacklist",
+                self.attachment_kind_blacklist,
+            )?,
+            attachment_only_blacklist: attachment_kinds(
+                "attachment_only_blacklist",
+                self.attachment_only_blacklist,
+            )?,
+            has_links: self.has_links,
+            min_body_length: self.min_body_length,
+            max_body_length: self.max_body_length,
+            body_length_unit: match self.body_length_unit.as_deref() {
+                None | Some("chars") => LengthUnit::Chars,
+                Some("words") => LengthUnit::Words,
+                Some(unit) => return Err(invalid("body_length_unit", unit)),
+            },
+            content_class_whitelist: content_classes(
+                "content_class_whitelist",
+                self.content_class_whitelist,
+            )?,
+            content_class_blacklist: content_classes(
+                "content_class_blacklist",
+                self.content_class_blacklist,
+            )?,
+            weekdays: self
+                .weekdays
+                .map(|ds| {
+                    ds.iter()
+                        .map(|d| d.parse::<Weekday>().map_err(|_| invalid("weekdays", d)))
+                        .collect()
+                })
+                .transpose()?,
+            time_of_day: self
+                .time_of_day
+                .map(|w| parse_time_window(&w).ok_or_else(|| invalid("time_of_day", w)))
+                .transpose()?,
+            dump_timezone: timezone("dump_timezone", self.dump_timezone)?,
+            timezone: timezone("timezone", self.timezone)?,
+            wordlist,
+            mask_wordlist: self.mask_wordlist.unwrap_or(false),
+            predicate: self
+                .query
+                .map(|q| Predicate::parse(&q).map_err(|e| invalid("query", e)))
+                .transpose()?,
+            dedup,
+            near_duplicates,
+            sample,
+        })
+    }
+}
+
+fn owned_names(names: Vec<String>) -> BTreeSet<Cow<'static, str>> {
+    names.into_iter().map(Cow::Owned).collect()
+}
+
+fn attachment_kinds(
+    field: &'static str,
+    names: Option<Vec<String>>,
+) -> Result<Option<BTreeSet<MessageAttachmentKind>>, ConfigError> {
+    names
+        .map(|ns| {
+            ns.iter()
+                .map(|n| MessageAttachmentKind::from_name(n).ok_or_else(|| invalid(field, n)))
+                .collect()
+        })
+        .transpose()
+}
+
+fn content_classes(
+    field: &'static str,
+    names: Option<Vec<String>>,
+) -> Result<Option<BTreeSet<ContentClass>>, ConfigError> {
+    names
+        .map(|ns| {
+            ns.iter()
+                .map(|n| ContentClass::from_name(n).ok_or_else(|| invalid(field, n)))
+                .collect()
+        })
+        .transpose()
+}
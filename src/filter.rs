@@ -1,17 +1,340 @@
-use crate::reader::MessageEvent;
-use chrono::NaiveDateTime;
-use std::collections::BTreeSet;
+use crate::near_duplicates::NearDuplicates;
+use crate::query::Predicate;
+use crate::reader::{EventResult, MessageAttachmentKind, MessageEvent, OwnedMessageEvent};
+use crate::wordlist::Wordlist;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, ParseResult, TimeZone, Weekday};
+use chrono_tz::Tz;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ops::{Bound, RangeBounds};
+
+lazy_static! {
+    static ref LINK_RE: Regex = Regex::new(r"(?i)\b(?:https?://|www\.)\S+").unwrap();
+    // Pictographs with skin tone modifiers, flags, joiners and variation selectors
+    static ref EMOJI_ONLY_RE: Regex = Regex::new(concat!(
+        r"^(?:\s|\p{Extended_Pictographic}|\p{Emoji_Modifier}|\p{Regional_Indicator}",
+        r"|\u{200D}|\u{FE0F})+$"
+    ))
+    .unwrap();
+    static ref BOT_COMMAND_RE: Regex = Regex::new(r"^\s*[/!]\w+").unwrap();
+}
+
+/// The format of message dates in VkOpt dumps, e.g. `2019.01.01 13:00:00`
+pub const DATE_FORMAT: &str = "%Y.%m.%d %H:%M:%S";
+
+pub fn parse_date(date: &str) -> ParseResult<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date, DATE_FORMAT)
+}
+
+/// Parses a daily window such as `09:00-18:00` (see `Filter::time_of_day`).
+pub fn parse_time_window(window: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let mut bounds = window.splitn(2, '-').map(|t| NaiveTime::parse_from_str(t, "%H:%M"));
+    match (bounds.next(), bounds.next()) {
+        (Some(Ok(start)), Some(Ok(end))) => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// How messages forwarded in other messages are treated.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum ForwardedPolicy {
+    /// Forwarded messages are checked like the rest;
+    /// rejecting a message rejects the messages forwarded in it
+    #[default]
+    Include,
+    Exclude,
+    /// Forwarded messages nested deeper than the given level are rejected
+    MaxDepth(u32),
+    /// Forwarded messages are checked on their own, regardless of the message they are forwarded
+    /// in, so that messages of the included authors are kept when quoted by others.
+    /// Requires a `MessageBuffer` or `Filter::wrap`, since `EventResult::SkipMessage` skips
+    /// forwarded messages
+    MatchingAuthors,
+}
+
+impl ForwardedPolicy {
+    /// Names of the policies other than `MaxDepth`, as used by the `--forwarded` CLI option.
+    pub const NAMES: [&'static str; 3] = ["none", "all", "matching_authors"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(ForwardedPolicy::Exclude),
+            "all" => Some(ForwardedPolicy::Include),
+            "matching_authors" => Some(ForwardedPolicy::MatchingAuthors),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum LengthUnit {
+    #[default]
+    Chars,
+    Words,
+}
+
+/// Kinds of messages that carry little text, see `BufferedMessage::content_classes`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub enum ContentClass {
+    EmojiOnly,
+    LinkOnly,
+    AttachmentOnly,
+    // Starts with /cmd or !cmd
+    BotCommand,
+}
+
+impl ContentClass {
+    pub const ALL: [ContentClass; 4] = [
+        ContentClass::EmojiOnly,
+        ContentClass::LinkOnly,
+        ContentClass::AttachmentOnly,
+        ContentClass::BotCommand,
+    ];
+
+    /// A lowercase name suitable for command line options, e.g. `emoji_only`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentClass::EmojiOnly => "emoji_only",
+            ContentClass::LinkOnly => "link_only",
+            ContentClass::AttachmentOnly => "attachment_only",
+            ContentClass::BotCommand => "bot_command",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|class| class.name() == name)
+    }
+}
+
+/// Keeps a reproducible subset of the messages that pass the other criteria.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    kind: SampleKind,
+    // Messages seen so far, shared by all the files read with the filter
+    seen: Cell<u64>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum SampleKind {
+    EveryNth(u64),
+    Fraction { fraction: f64, seed: u64 },
+}
+
+impl Sample {
+    /// Keeps the Nth, 2Nth, ... message, counting across all files in the order they are read.
+    pub fn every_nth(n: u64) -> Self {
+        Sample { kind: SampleKind::EveryNth(n.max(1)), seen: Cell::new(0) }
+    }
+
+    /// Keeps about `fraction` of the messages, picked by a seeded hash of the author, date and
+    /// text of each message, so the result does not depend on how the dump is split into files.
+    pub fn fraction(fraction: f64, seed: u64) -> Self {
+        Sample { kind: SampleKind::Fraction { fraction, seed }, seen: Cell::new(0) }
+    }
+
+    fn keeps(&self, message: &BufferedMessage) -> bool {
+        match self.kind {
+            SampleKind::EveryNth(n) => {
+                let seen = self.seen.get() + 1;
+                self.seen.set(seen);
+                seen % n == 0
+            }
+            SampleKind::Fraction { fraction, seed } => {
+                let mut hash = Fnv1a::new(seed);
+                hash.write(message.short_name().unwrap_or("").as_bytes());
+                hash.write(message.date().unwrap_or("").as_bytes());
+                hash.write(message.body().as_bytes());
+                // The top 53 bits as a uniformly distributed number in [0, 1)
+                let point = (hash.finish() >> 11) as f64 / (1u64 << 53) as f64;
+                point < fraction
+            }
+        }
+    }
+}
+
+/// Drops messages repeating the text of one of the last `window` messages that reached it,
+/// which are the messages passing the other criteria (except sampling).
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    window: usize,
+    text: DedupText,
+    scope: DedupScope,
+    // Hashes of the recent texts by author, or under "" for DedupScope::Global
+    recent: RefCell<HashMap<String, VecDeque<u64>>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DedupText {
+    #[default]
+    Exact,
+    // Ignores case, punctuation and whitespace, so "Hi, Denko!" repeats "hi denko"
+    Normalized,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DedupScope {
+    #[default]
+    Global,
+    // Only messages of the same author (by short name) count as repeats
+    PerAuthor,
+}
+
+impl Dedup {
+    pub fn new(window: usize, text: DedupText, scope: DedupScope) -> Self {
+        Dedup { window: window.max(1), text, scope, recent: RefCell::new(HashMap::new()) }
+    }
+
+    fn keeps(&self, message: &BufferedMessage) -> bool {
+        let body = message.body();
+        let exact = body.trim();
+        let normalized;
+        let text = match self.text {
+            DedupText::Exact => exact,
+            DedupText::Normalized => {
+                normalized = normalize_text(exact);
+                // Texts without letters or digits, e.g. emoji, are compared as they are
+                if normalized.is_empty() { exact } else { &normalized }
+            }
+        };
+        // Messages without text are never repeats
+        if text.is_empty() {
+            return true;
+        }
+        let mut hash = Fnv1a::new(0);
+        hash.write(text.as_bytes());
+        let hash = hash.finish();
+
+        let author = match self.scope {
+            DedupScope::Global => "",
+            DedupScope::PerAuthor => message.short_name().unwrap_or(""),
+        };
+        let mut recent = self.recent.borrow_mut();
+        let recent = recent.entry(author.to_owned()).or_default();
+        let repeated = recent.contains(&hash);
+        // Repeats stay in the window, so a flood is dropped for as long as it goes on
+        recent.push_back(hash);
+        if recent.len() > self.window {
+            recent.pop_front();
+        }
+        !repeated
+    }
+}
+
+pub(crate) fn normalize_text(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .flat_map(char::to_lowercase)
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// FNV-1a with a splitmix64 finalizer; unlike std's hashers, the output is stable across releases
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut hash = Fnv1a(0xcbf2_9ce4_8422_2325);
+        hash.write(&seed.to_le_bytes());
+        hash
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        // Separates the fields, so that ("ab", "c") and ("a", "bc") hash differently
+        self.0 = (self.0 ^ 0xff).wrapping_mul(0x0100_0000_01b3);
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        mix(self.0)
+    }
+}
+
+// The splitmix64 finalizer
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
 
 #[derive(Default)]
 pub struct Filter<'a> {
+    pub forwarded: ForwardedPolicy,
+    // Inclusive bounds
     pub since_date: Option<NaiveDateTime>,
-    pub short_name_whitelist: Option<BTreeSet<&'a str>>,
-    pub short_name_blacklist: Option<BTreeSet<&'a str>>,
+    pub until_date: Option<NaiveDateTime>,
+    // Arbitrary bounds, checked in addition to since_date and until_date
+    pub date_range: Option<(Bound<NaiveDateTime>, Bound<NaiveDateTime>)>,
+    pub short_name_whitelist: Option<BTreeSet<Cow<'a, str>>>,
+    pub short_name_blacklist: Option<BTreeSet<Cow<'a, str>>>,
+    pub full_name_whitelist: Option<BTreeSet<Cow<'a, str>>>,
+    pub full_name_blacklist: Option<BTreeSet<Cow<'a, str>>>,
+    // Short and full name lists may contain `*` (any characters) and `?` (one character)
+    pub name_globs: bool,
+    pub name_case_insensitive: bool,
+    // Numeric ids do not change with short names, but are missing for authors with a custom
+    // profile address, whose messages are rejected by the whitelist (see `AuthorIdExtracted`)
+    pub author_id_whitelist: Option<BTreeSet<i64>>,
+    pub author_id_blacklist: Option<BTreeSet<i64>>,
+    // Messages mentioning (`[id1|Name]`) any of these users pass; when both sets are given,
+    // a mention matching either of them is enough
+    pub mention_id_whitelist: Option<BTreeSet<i64>>,
+    pub mention_name_whitelist: Option<BTreeSet<Cow<'a, str>>>,
+    // Matched against the whole message body, so the events have to go through a MessageBuffer
+    pub body_whitelist: Option<Regex>,
+    pub body_blacklist: Option<Regex>,
+    // Attachment criteria are message-level too; attachments of reposted wall posts are not counted
+    pub has_attachments: Option<bool>,
+    // Messages with at least one attachment of these kinds pass
+    pub attachment_kind_whitelist: Option<BTreeSet<MessageAttachmentKind>>,
+    // Messages with any attachment of these kinds are rejected
+    pub attachment_kind_blacklist: Option<BTreeSet<MessageAttachmentKind>>,
+    // Messages without text that only have attachments of these kinds (e.g. stickers) are rejected
+    pub attachment_only_blacklist: Option<BTreeSet<MessageAttachmentKind>>,
+    pub has_links: Option<bool>,
+    // Inclusive bounds on the length of the trimmed body
+    pub min_body_length: Option<usize>,
+    pub max_body_length: Option<usize>,
+    pub body_length_unit: LengthUnit,
+    // Messages of at least one of these classes pass
+    pub content_class_whitelist: Option<BTreeSet<ContentClass>>,
+    // Messages of any of these classes are rejected
+    pub content_class_blacklist: Option<BTreeSet<ContentClass>>,
+    // Weekdays and times of day are evaluated in `timezone`, converting message dates from
+    // `dump_timezone` (the timezone of the browser the dump was exported in) when both are set
+    pub weekdays: Option<HashSet<Weekday>>,
+    // Start inclusive, end exclusive; a window with start > end wraps around midnight (22:00-06:00)
+    pub time_of_day: Option<(NaiveTime, NaiveTime)>,
+    pub dump_timezone: Option<Tz>,
+    pub timezone: Option<Tz>,
+    // Messages with listed words in the body are rejected, or have the words masked in their
+    // `BodyPartExtracted` events with `mask_wordlist`
+    pub wordlist: Option<Wordlist>,
+    pub mask_wordlist: bool,
+    // Checked in addition to the other criteria, see `Predicate::parse` for the query syntax
+    pub predicate: Option<Predicate>,
+    pub dedup: Option<Dedup>,
+    pub near_duplicates: Option<NearDuplicates>,
+    // Applied to the messages that pass all other criteria
+    pub sample: Option<Sample>,
 }
 
 impl<'a> Filter<'a> {
+    /// Checks the criteria that apply to single events: forwarded levels, names, author ids and
+    /// dates. The rest need whole messages (see `MessageBuffer`), and so do messages without
+    /// a numeric author id, which are only rejected by `author_id_whitelist` there.
     pub fn filter_event<'e>(&self, event: MessageEvent<'e>) -> Option<MessageEvent<'e>> {
         match event {
+            MessageEvent::Start(level) if level_passes(self, level) => Some(event),
+            MessageEvent::Start(_) => None,
+            MessageEvent::FullNameExtracted(name) if full_name_passes(self, name) => Some(event),
+            MessageEvent::FullNameExtracted(_) => None,
+            MessageEvent::AuthorIdExtracted(id) if author_id_passes(self, Some(id)) => Some(event),
+            MessageEvent::AuthorIdExtracted(_) => None,
             MessageEvent::ShortNameExtracted(name) if short_name_passes(self, name) => Some(event),
             MessageEvent::ShortNameExtracted(_) => None,
             MessageEvent::DateExtracted(date) if date_passes(self, date) => Some(event),
@@ -19,26 +342,495 @@ impl<'a> Filter<'a> {
             _ => Some(event),
         }
     }
+
+    /// Returns a reducer for `fold_html` that passes the events of accepted messages to `reducer`,
+    /// followed by `DumpEnd`.
+    ///
+    /// Messages are buffered (see `MessageBuffer`), so that all criteria and forwarded policies
+    /// apply. As in `fold_html`, `EventResult::SkipMessage` returned by `reducer` skips the rest
+    /// of the message and the messages forwarded in it.
+    pub fn wrap<'f, A, F>(
+        &'f self,
+        mut reducer: F,
+    ) -> impl FnMut(A, MessageEvent) -> EventResult<A> + 'f
+    where
+        A: 'f,
+        F: FnMut(A, MessageEvent) -> EventResult<A> + 'f,
+    {
+        let filter: &'f Filter<'f> = self;
+        let mut buffer = MessageBuffer::new(filter);
+        let mut skip_level = None;
+        move |mut acc, event| {
+            let end = matches!(event, MessageEvent::DumpEnd);
+            if let Some(message) = buffer.push(event) {
+                if skip_level.is_none_or(|level| message.level <= level) {
+                    skip_level = None;
+                    for e in message.events.iter() {
+                        match reducer(acc, e.as_event()) {
+                            EventResult::Consumed(a) => acc = a,
+                            EventResult::SkipMessage(a) => {
+                                acc = a;
+                                skip_level = Some(message.level);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            if end {
+                skip_level = None;
+                acc = match reducer(acc, MessageEvent::DumpEnd) {
+                    EventResult::Consumed(a) | EventResult::SkipMessage(a) => a,
+                };
+            }
+            EventResult::Consumed(acc)
+        }
+    }
+
+    /// Checks the criteria that depend on the whole message (see `MessageBuffer`).
+    pub fn message_passes(&self, message: &BufferedMessage) -> bool {
+        author_id_passes(self, message.author_id())
+            && mentions_pass(self, message)
+            && body_passes(self, message)
+            && attachments_pass(self, message)
+            && body_length_passes(self, message)
+            && content_classes_pass(self, message)
+            && wordlist_passes(self, message)
+            && self.predicate.as_ref().is_none_or(|p| p.matches(message))
+            && self.dedup.as_ref().is_none_or(|d| d.keeps(message))
+            && self.near_duplicates.as_ref().is_none_or(|n| n.keeps(message))
+            && self.sample.as_ref().is_none_or(|s| s.keeps(message))
+    }
 }
 
-fn short_name_passes<'a>(filter: &Filter<'a>, short_name: &'a str) -> bool {
-    if let Some(ref whitelist) = filter.short_name_whitelist {
-        whitelist.contains(short_name)
+fn author_id_passes<'a>(filter: &Filter<'a>, id: Option<i64>) -> bool {
+    let whitelisted = |w: &BTreeSet<i64>| id.is_some_and(|id| w.contains(&id));
+    let blacklisted = |b: &BTreeSet<i64>| id.is_some_and(|id| b.contains(&id));
+    filter.author_id_whitelist.as_ref().is_none_or(whitelisted)
+        && !filter.author_id_blacklist.as_ref().is_some_and(blacklisted)
+}
+
+fn mentions_pass<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    let (ids, names) = (&filter.mention_id_whitelist, &filter.mention_name_whitelist);
+    if ids.is_none() && names.is_none() {
+        return true;
+    }
+    message.mentions().any(|(id, name)| {
+        ids.as_ref().is_some_and(|ids| ids.contains(&id))
+            || names.as_ref().is_some_and(|names| names.contains(name))
+    })
+}
+
+fn wordlist_passes<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    // Masked messages are let through
+    filter.mask_wordlist || filter.wordlist.as_ref().is_none_or(|w| !w.matches(&message.body()))
+}
+
+fn body_passes<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    let no_criteria = filter.body_whitelist.is_none() && filter.body_blacklist.is_none();
+    if no_criteria && filter.has_links.is_none() {
+        return true;
+    }
+    let body = message.body();
+    if let Some(ref whitelist) = filter.body_whitelist {
+        if !whitelist.is_match(&body) {
+            return false;
+        }
+    }
+    if let Some(ref blacklist) = filter.body_blacklist {
+        if blacklist.is_match(&body) {
+            return false;
+        }
+    }
+    if let Some(has_links) = filter.has_links {
+        LINK_RE.is_match(&body) == has_links
+    }
+    else {
+        true
+    }
+}
+
+fn attachments_pass<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    let kinds = message.attachment_kinds();
+    if let Some(has_attachments) = filter.has_attachments {
+        if kinds.is_empty() == has_attachments {
+            return false;
+        }
+    }
+    if let Some(ref whitelist) = filter.attachment_kind_whitelist {
+        if !kinds.iter().any(|k| whitelist.contains(k)) {
+            return false;
+        }
+    }
+    if let Some(ref blacklist) = filter.attachment_kind_blacklist {
+        if kinds.iter().any(|k| blacklist.contains(k)) {
+            return false;
+        }
     }
-    else if let Some(ref blacklist) = filter.short_name_blacklist {
-        !blacklist.contains(short_name)
+    if let Some(ref blacklist) = filter.attachment_only_blacklist {
+        let attachments_only = !kinds.is_empty() && message.body().trim().is_empty();
+        !(attachments_only && kinds.iter().all(|k| blacklist.contains(k)))
     }
     else {
         true
     }
 }
 
+fn body_length_passes<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    if filter.min_body_length.is_none() && filter.max_body_length.is_none() {
+        return true;
+    }
+    let length = message.body_length(filter.body_length_unit);
+    filter.min_body_length.is_none_or(|min| length >= min)
+        && filter.max_body_length.is_none_or(|max| length <= max)
+}
+
+fn content_classes_pass<'a>(filter: &Filter<'a>, message: &BufferedMessage) -> bool {
+    if filter.content_class_whitelist.is_none() && filter.content_class_blacklist.is_none() {
+        return true;
+    }
+    let classes = message.content_classes();
+    if let Some(ref whitelist) = filter.content_class_whitelist {
+        if !classes.iter().any(|c| whitelist.contains(c)) {
+            return false;
+        }
+    }
+    if let Some(ref blacklist) = filter.content_class_blacklist {
+        !classes.iter().any(|c| blacklist.contains(c))
+    }
+    else {
+        true
+    }
+}
+
+/// The events of a single message (excluding the messages forwarded in it).
+#[derive(Debug, PartialEq, Clone)]
+pub struct BufferedMessage {
+    pub level: u32,
+    pub events: Vec<OwnedMessageEvent>,
+}
+
+impl BufferedMessage {
+    pub fn body(&self) -> String {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                OwnedMessageEvent::BodyPartExtracted(part) => Some(part.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Kinds of the message's own attachments, excluding those of reposted wall posts.
+    pub fn attachment_kinds(&self) -> Vec<MessageAttachmentKind> {
+        let mut in_wall = false;
+        let mut kinds = Vec::new();
+        for event in self.events.iter() {
+            match event {
+                OwnedMessageEvent::WallAttachmentsStart => in_wall = true,
+                OwnedMessageEvent::WallAttachmentsEnd => in_wall = false,
+                OwnedMessageEvent::AttachmentExtracted { kind, .. } if !in_wall => {
+                    kinds.push(*kind)
+                }
+                _ => {}
+            }
+        }
+        kinds
+    }
+
+    pub fn body_length(&self, unit: LengthUnit) -> usize {
+        let body = self.body();
+        match unit {
+            LengthUnit::Chars => body.trim().chars().count(),
+            LengthUnit::Words => body.split_whitespace().count(),
+        }
+    }
+
+    pub fn content_classes(&self) -> Vec<ContentClass> {
+        let body = self.body();
+        let text = body.trim();
+        let mut classes = Vec::new();
+        if EMOJI_ONLY_RE.is_match(text) {
+            classes.push(ContentClass::EmojiOnly);
+        }
+        if LINK_RE.is_match(text) && LINK_RE.replace_all(text, "").trim().is_empty() {
+            classes.push(ContentClass::LinkOnly);
+        }
+        if text.is_empty() && !self.attachment_kinds().is_empty() {
+            classes.push(ContentClass::AttachmentOnly);
+        }
+        if BOT_COMMAND_RE.is_match(text) {
+            classes.push(ContentClass::BotCommand);
+        }
+        classes
+    }
+
+    pub fn has_links(&self) -> bool {
+        LINK_RE.is_match(&self.body())
+    }
+
+    pub fn full_name(&self) -> Option<&str> {
+        self.events.iter().find_map(|e| match e {
+            OwnedMessageEvent::FullNameExtracted(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The number in the id of the message in the dump; forwarded messages have none
+    pub fn id(&self) -> Option<u64> {
+        self.events.iter().find_map(|e| match e {
+            OwnedMessageEvent::MessageIdExtracted(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    pub fn author_id(&self) -> Option<i64> {
+        self.events.iter().find_map(|e| match e {
+            OwnedMessageEvent::AuthorIdExtracted(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Ids and names of the users mentioned in the message body
+    pub fn mentions(&self) -> impl Iterator<Item = (i64, &str)> {
+        self.events.iter().filter_map(|e| match e {
+            OwnedMessageEvent::MentionExtracted { id, name } => Some((*id, name.as_str())),
+            _ => None,
+        })
+    }
+
+    pub fn short_name(&self) -> Option<&str> {
+        self.events.iter().find_map(|e| match e {
+            OwnedMessageEvent::ShortNameExtracted(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.events.iter().find_map(|e| match e {
+            OwnedMessageEvent::DateExtracted(date) => Some(date.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Collects the events of each message until the filter can decide on it,
+/// which is only possible at the start of the next message (or at the end of the dump)
+/// for criteria that depend on the whole message.
+///
+/// Like `EventResult::SkipMessage`, rejecting a message rejects the messages forwarded in it,
+/// unless the filter uses `ForwardedPolicy::MatchingAuthors`.
+pub struct MessageBuffer<'f, 'a> {
+    filter: &'f Filter<'a>,
+    message: Option<BufferedMessage>,
+    rejected_level: Option<u32>,
+}
+
+impl<'f, 'a> MessageBuffer<'f, 'a> {
+    pub fn new(filter: &'f Filter<'a>) -> Self {
+        MessageBuffer { filter, message: None, rejected_level: None }
+    }
+
+    /// Returns the previous message once it is complete, if it passes the filter.
+    pub fn push(&mut self, event: MessageEvent) -> Option<BufferedMessage> {
+        match event {
+            MessageEvent::Start(level) => {
+                let complete = self.flush();
+                match self.rejected_level {
+                    Some(rejected_level) if level > rejected_level => {}
+                    _ if !level_passes(self.filter, level) => {
+                        self.rejected_level = None;
+                        self.reject(level);
+                    }
+                    _ => {
+                        self.rejected_level = None;
+                        self.message = Some(BufferedMessage {
+                            level,
+                            events: vec![OwnedMessageEvent::Start(level)],
+                        });
+                    }
+                }
+                complete
+            }
+            MessageEvent::DumpEnd => {
+                let complete = self.flush();
+                self.rejected_level = None;
+                complete
+            }
+            _ => {
+                if let Some(ref mut message) = self.message {
+                    match self.filter.filter_event(event) {
+                        Some(e) => message.events.push(e.into_owned()),
+                        None => {
+                            let level = message.level;
+                            self.message = None;
+                            self.reject(level);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Option<BufferedMessage> {
+        let mut message = self.message.take()?;
+        if self.filter.message_passes(&message) {
+            if let (Some(wordlist), true) = (&self.filter.wordlist, self.filter.mask_wordlist) {
+                for event in message.events.iter_mut() {
+                    if let OwnedMessageEvent::BodyPartExtracted(part) = event {
+                        if let Cow::Owned(masked) = wordlist.mask(part) {
+                            *part = masked;
+                        }
+                    }
+                }
+            }
+            Some(message)
+        }
+        else {
+            self.reject(message.level);
+            None
+        }
+    }
+
+    fn reject(&mut self, level: u32) {
+        if self.filter.forwarded != ForwardedPolicy::MatchingAuthors {
+            self.rejected_level = Some(level);
+        }
+    }
+}
+
+fn level_passes<'a>(filter: &Filter<'a>, level: u32) -> bool {
+    match filter.forwarded {
+        ForwardedPolicy::Exclude => level == 0,
+        ForwardedPolicy::MaxDepth(max_level) => level <= max_level,
+        ForwardedPolicy::Include | ForwardedPolicy::MatchingAuthors => true,
+    }
+}
+
+fn short_name_passes<'a>(filter: &Filter<'a>, short_name: &'a str) -> bool {
+    name_passes(filter, &filter.short_name_whitelist, &filter.short_name_blacklist, short_name)
+}
+
+fn full_name_passes<'a>(filter: &Filter<'a>, full_name: &'a str) -> bool {
+    name_passes(filter, &filter.full_name_whitelist, &filter.full_name_blacklist, full_name)
+}
+
+fn name_passes<'a>(
+    filter: &Filter<'a>,
+    whitelist: &Option<BTreeSet<Cow<'a, str>>>,
+    blacklist: &Option<BTreeSet<Cow<'a, str>>>,
+    name: &str,
+) -> bool {
+    let listed = |list: &BTreeSet<Cow<'a, str>>| {
+        if !filter.name_globs && !filter.name_case_insensitive {
+            return list.contains(name);
+        }
+        let name = fold_case(filter, name);
+        list.iter().any(|pattern| {
+            let pattern = fold_case(filter, pattern);
+            if filter.name_globs {
+                glob_matches(&pattern, &name)
+            }
+            else {
+                pattern == name
+            }
+        })
+    };
+    // Both lists apply when set: a whitelisted name can still be blacklisted
+    whitelist.as_ref().is_none_or(listed) && !blacklist.as_ref().is_some_and(listed)
+}
+
+fn fold_case<'n>(filter: &Filter, name: &'n str) -> Cow<'n, str> {
+    if filter.name_case_insensitive {
+        Cow::Owned(name.to_lowercase())
+    }
+    else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any sequence of characters
+/// and `?` for a single character.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` consume one more character
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 fn date_passes<'a>(filter: &Filter<'a>, date: &'a str) -> bool {
-    if let Some(ref since_date) = filter.since_date {
-        let msg_date = NaiveDateTime::parse_from_str(date, "%Y.%m.%d %H:%M:%S").unwrap();
-        msg_date >= *since_date
+    let no_bounds =
+        filter.since_date.is_none() && filter.until_date.is_none() && filter.date_range.is_none();
+    if no_bounds && filter.weekdays.is_none() && filter.time_of_day.is_none() {
+        return true;
+    }
+    let msg_date = parse_date(date).unwrap();
+    if let Some(since_date) = filter.since_date {
+        if msg_date < since_date {
+            return false;
+        }
+    }
+    if let Some(until_date) = filter.until_date {
+        if msg_date > until_date {
+            return false;
+        }
+    }
+    if let Some(date_range) = filter.date_range {
+        if !date_range.contains(&msg_date) {
+            return false;
+        }
+    }
+    let local_date = local_date(filter, msg_date);
+    if let Some(ref weekdays) = filter.weekdays {
+        if !weekdays.contains(&local_date.weekday()) {
+            return false;
+        }
+    }
+    if let Some((start, end)) = filter.time_of_day {
+        let time = local_date.time();
+        if start <= end {
+            start <= time && time < end
+        }
+        else {
+            start <= time || time < end
+        }
     }
     else {
         true
     }
 }
+
+fn local_date<'a>(filter: &Filter<'a>, date: NaiveDateTime) -> NaiveDateTime {
+    match (filter.dump_timezone, filter.timezone) {
+        (Some(dump_tz), Some(tz)) => match dump_tz.from_local_datetime(&date).earliest() {
+            Some(dump_date) => dump_date.with_timezone(&tz).naive_local(),
+            // Local times skipped by a DST transition cannot occur in a dump
+            None => date,
+        },
+        _ => date,
+    }
+}
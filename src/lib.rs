@@ -1,5 +1,10 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod attachment;
+pub mod config;
 pub mod filter;
+pub mod near_duplicates;
+pub mod query;
 pub mod reader;
+pub mod wordlist;
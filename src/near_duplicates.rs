@@ -0,0 +1,163 @@
+use crate::filter::{mix, normalize_text, BufferedMessage, Fnv1a};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+// 128 MinHash values split into 32 LSH bands of 4 rows: a pair with a similarity of 0.5 shares
+// a band with a probability of 0.87, pairs above 0.7 almost always do
+const BANDS: usize = 32;
+const ROWS: usize = 4;
+const HASHES: usize = BANDS * ROWS;
+const SHINGLE_CHARS: usize = 4;
+
+/// Finds messages whose text is similar to the text of an earlier message (near duplicates),
+/// e.g. lightly edited copypastas.
+///
+/// Similarity is the Jaccard index of the character 4-grams of the texts, estimated with MinHash;
+/// locality-sensitive hashing picks the earlier messages a message is compared to. Case,
+/// punctuation and whitespace are ignored, and messages without letters or digits are skipped.
+/// Only the messages that represent an LSH bucket keep their signature, author, date and text;
+/// the rest are recorded when they join a cluster.
+#[derive(Debug, Clone)]
+pub struct NearDuplicates {
+    threshold: f64,
+    drop_duplicates: bool,
+    state: RefCell<State>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    // Union-find over message indexes; the root of a cluster is its first message
+    parents: Vec<usize>,
+    buckets: HashMap<(usize, u64), Vec<usize>>,
+    // Messages in the buckets, which later messages are compared to
+    entries: HashMap<usize, Entry>,
+    // Messages that joined a cluster
+    members: BTreeMap<usize, ClusterMember>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    signature: [u32; HASHES],
+    member: ClusterMember,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterMember {
+    pub short_name: String,
+    pub date: String,
+    pub body: String,
+}
+
+impl NearDuplicates {
+    /// `threshold` is the minimum similarity (from 0 to 1) of near duplicates. With
+    /// `drop_duplicates`, `Filter` only passes the first message of each cluster.
+    pub fn new(threshold: f64, drop_duplicates: bool) -> Self {
+        NearDuplicates { threshold, drop_duplicates, state: RefCell::new(State::default()) }
+    }
+
+    /// Records the message and returns whether it is a near duplicate of an earlier one.
+    pub fn push(&self, message: &BufferedMessage) -> bool {
+        let signature = match signature(&normalize_text(&message.body())) {
+            Some(signature) => signature,
+            None => return false,
+        };
+        let mut state = self.state.borrow_mut();
+        let State { parents, buckets, entries, members } = &mut *state;
+        let index = parents.len();
+        parents.push(index);
+        let member = || ClusterMember {
+            short_name: message.short_name().unwrap_or("").to_owned(),
+            date: message.date().unwrap_or("").to_owned(),
+            body: message.body().trim().to_owned(),
+        };
+
+        let mut duplicate = false;
+        let mut entered = false;
+        for band in 0..BANDS {
+            let mut hash = Fnv1a::new(band as u64);
+            for value in &signature[band * ROWS..(band + 1) * ROWS] {
+                hash.write(&value.to_le_bytes());
+            }
+            let bucket = buckets.entry((band, hash.finish())).or_default();
+            let mut matched = false;
+            for &candidate in bucket.iter() {
+                let entry = &entries[&candidate];
+                if similarity(&entry.signature, &signature) >= self.threshold {
+                    union(parents, candidate, index);
+                    members.entry(candidate).or_insert_with(|| entry.member.clone());
+                    matched = true;
+                }
+            }
+            // A similar message already represents the bucket, which keeps the buckets
+            // of floods small
+            if !matched {
+                bucket.push(index);
+            }
+            duplicate |= matched;
+            entered |= !matched;
+        }
+        if duplicate {
+            members.insert(index, member());
+        }
+        if entered {
+            entries.insert(index, Entry { signature, member: member() });
+        }
+        duplicate
+    }
+
+    /// Clusters of near duplicates found so far, in the order of their first messages.
+    pub fn clusters(&self) -> Vec<Vec<ClusterMember>> {
+        let mut state = self.state.borrow_mut();
+        let State { parents, members, .. } = &mut *state;
+        let mut clusters: BTreeMap<usize, Vec<ClusterMember>> = BTreeMap::new();
+        for (&index, member) in members.iter() {
+            clusters.entry(find(parents, index)).or_default().push(member.clone());
+        }
+        clusters.into_values().collect()
+    }
+
+    pub(crate) fn keeps(&self, message: &BufferedMessage) -> bool {
+        let duplicate = self.push(message);
+        !(duplicate && self.drop_duplicates)
+    }
+}
+
+fn signature(text: &str) -> Option<[u32; HASHES]> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+    let mut signature = [u32::MAX; HASHES];
+    let mut shingle = String::new();
+    // Texts shorter than a shingle make up a single one
+    for window in chars.windows(SHINGLE_CHARS.min(chars.len())) {
+        shingle.clear();
+        shingle.extend(window);
+        let mut hash = Fnv1a::new(0);
+        hash.write(shingle.as_bytes());
+        let hash = hash.finish();
+        for (i, min) in signature.iter_mut().enumerate() {
+            // A different permutation of the hash space for each of the values
+            let value = mix(hash ^ (i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)) as u32;
+            *min = (*min).min(value);
+        }
+    }
+    Some(signature)
+}
+
+fn similarity(a: &[u32; HASHES], b: &[u32; HASHES]) -> f64 {
+    a.iter().zip(b.iter()).filter(|(a, b)| a == b).count() as f64 / HASHES as f64
+}
+
+fn find(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
@@ -0,0 +1,294 @@
+use crate::filter::{parse_date, BufferedMessage};
+use crate::reader::MessageAttachmentKind;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use std::fmt;
+
+/// A composable message predicate, usually parsed from a query such as
+/// `author:sota and date>=2019.01.01 and not has:sticker`.
+///
+/// Supported terms:
+/// * `author:<short name>`, `name:<full name>`
+/// * `date<op><date>`, where `<op>` is one of `:`, `=`, `<`, `<=`, `>`, `>=`
+///   and the date is either `2019.01.01` or `"2019.01.01 13:00:00"`
+/// * `level<op><number>`, the nesting level of forwarded messages
+/// * `has:<attachment kind>` (e.g. `has:sticker`), `has:attachment`, `has:link`
+/// * `body:<regex>`
+///
+/// Terms are combined with `and`, `or`, `not` and parentheses; values containing spaces are quoted.
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+    ShortName(String),
+    FullName(String),
+    Date(Comparison, NaiveDateTime),
+    Level(Comparison, u32),
+    // None matches any attachment
+    HasAttachment(Option<MessageAttachmentKind>),
+    HasLink,
+    Body(Regex),
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Comparison {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl Predicate {
+    pub fn parse(query: &str) -> Result<Predicate, QueryError> {
+        let mut parser = Parser { query, pos: 0 };
+        let predicate = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < query.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(predicate)
+    }
+
+    pub fn and(self, other: Predicate) -> Predicate {
+        Predicate::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Predicate) -> Predicate {
+        Predicate::Or(Box::new(self), Box::new(other))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Predicate {
+        Predicate::Not(Box::new(self))
+    }
+
+    pub fn matches(&self, message: &BufferedMessage) -> bool {
+        match self {
+            Predicate::And(lhs, rhs) => lhs.matches(message) && rhs.matches(message),
+            Predicate::Or(lhs, rhs) => lhs.matches(message) || rhs.matches(message),
+            Predicate::Not(p) => !p.matches(message),
+            Predicate::ShortName(name) => message.short_name() == Some(name.as_str()),
+            Predicate::FullName(name) => message.full_name() == Some(name.as_str()),
+            Predicate::Date(cmp, date) => match message.date().map(parse_date) {
+                Some(Ok(msg_date)) => cmp.holds(msg_date, *date),
+                _ => false,
+            },
+            Predicate::Level(cmp, level) => cmp.holds(message.level, *level),
+            Predicate::HasAttachment(None) => !message.attachment_kinds().is_empty(),
+            Predicate::HasAttachment(Some(kind)) => message.attachment_kinds().contains(kind),
+            Predicate::HasLink => message.has_links(),
+            Predicate::Body(re) => re.is_match(&message.body()),
+        }
+    }
+}
+
+struct Parser<'q> {
+    query: &'q str,
+    pos: usize,
+}
+
+impl<'q> Parser<'q> {
+    fn error(&self, message: &str) -> QueryError {
+        QueryError { position: self.pos, message: message.to_owned() }
+    }
+
+    fn rest(&self) -> &'q str {
+        &self.query[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Keywords must be followed by a delimiter, so that e.g. `notes` is not read as `not es`
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let delimited = match self.rest().strip_prefix(keyword) {
+            Some(after) => after.chars().next().is_none_or(|c| c.is_whitespace() || c == '('),
+            None => false,
+        };
+        if delimited {
+            self.pos += keyword.len();
+        }
+        delimited
+    }
+
+    fn parse_or(&mut self) -> Result<Predicate, QueryError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_keyword("or") {
+            lhs = lhs.or(self.parse_and()?);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Predicate, QueryError> {
+        let mut lhs = self.parse_unary()?;
+        while self.eat_keyword("and") {
+            lhs = lhs.and(self.parse_unary()?);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Predicate, QueryError> {
+        if self.eat_keyword("not") {
+            Ok(self.parse_unary()?.not())
+        } else if self.eat("(") {
+            let inner = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected )"));
+            }
+            Ok(inner)
+        } else {
+            self.parse_term()
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Predicate, QueryError> {
+        self.skip_whitespace();
+        let key_start = self.pos;
+        let rest = self.rest();
+        let key_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        if key_len == 0 {
+            return Err(self.error("expected a term"));
+        }
+        let key = &self.query[key_start..key_start + key_len];
+        self.pos += key_len;
+
+        let cmp = self.parse_comparison()?;
+        let value_start = self.pos;
+        let value = self.parse_value()?;
+        let value_error = |message: &str| QueryError {
+            position: value_start,
+            message: message.to_owned(),
+        };
+
+        match (key, cmp) {
+            ("author", Comparison::Eq) => Ok(Predicate::ShortName(value)),
+            ("name", Comparison::Eq) => Ok(Predicate::FullName(value)),
+            ("body", Comparison::Eq) => Regex::new(&value)
+                .map(Predicate::Body)
+                .map_err(|e| value_error(&e.to_string())),
+            ("has", Comparison::Eq) => match value.as_str() {
+                "attachment" => Ok(Predicate::HasAttachment(None)),
+                "link" => Ok(Predicate::HasLink),
+                kind => MessageAttachmentKind::from_name(kind)
+                    .map(|k| Predicate::HasAttachment(Some(k)))
+                    .ok_or_else(|| value_error("unknown attachment kind")),
+            },
+            ("level", cmp) => value
+                .parse()
+                .map(|level| Predicate::Level(cmp, level))
+                .map_err(|_| value_error("expected a number")),
+            ("date", cmp) => {
+                if let Ok(date) = parse_date(&value) {
+                    Ok(Predicate::Date(cmp, date))
+                } else if let Ok(day) = NaiveDate::parse_from_str(&value, "%Y.%m.%d") {
+                    Ok(day_predicate(cmp, day))
+                } else {
+                    Err(value_error("expected a date (2019.01.01 or \"2019.01.01 13:00:00\")"))
+                }
+            }
+            ("author", _) | ("name", _) | ("body", _) | ("has", _) => Err(QueryError {
+                position: key_start,
+                message: format!("{} only supports : and =", key),
+            }),
+            _ => Err(QueryError { position: key_start, message: format!("unknown term {}", key) }),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Comparison, QueryError> {
+        for (token, cmp) in &[
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+            ("=", Comparison::Eq),
+            (":", Comparison::Eq),
+        ] {
+            if self.rest().starts_with(token) {
+                self.pos += token.len();
+                return Ok(*cmp);
+            }
+        }
+        Err(self.error("expected one of : = < <= > >="))
+    }
+
+    fn parse_value(&mut self) -> Result<String, QueryError> {
+        let rest = self.rest();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.pos += i + 2;
+                        return Ok(value);
+                    }
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    c => value.push(c),
+                }
+            }
+            Err(self.error("unterminated quoted value"))
+        } else {
+            let len = rest.find(|c: char| c.is_whitespace() || c == ')').unwrap_or(rest.len());
+            if len == 0 {
+                return Err(self.error("expected a value"));
+            }
+            self.pos += len;
+            Ok(rest[..len].to_owned())
+        }
+    }
+}
+
+// Comparisons with a day (without time) cover the whole day: date:2019.01.01 matches any time on it
+fn day_predicate(cmp: Comparison, day: NaiveDate) -> Predicate {
+    let start = day.and_hms_opt(0, 0, 0).unwrap();
+    let next_start = start + Duration::days(1);
+    match cmp {
+        Comparison::Eq => Predicate::Date(Comparison::Ge, start)
+            .and(Predicate::Date(Comparison::Lt, next_start)),
+        Comparison::Lt => Predicate::Date(Comparison::Lt, start),
+        Comparison::Ge => Predicate::Date(Comparison::Ge, start),
+        Comparison::Le => Predicate::Date(Comparison::Lt, next_start),
+        Comparison::Gt => Predicate::Date(Comparison::Ge, next_start),
+    }
+}
@@ -1,3 +1,4 @@
+use crate::attachment::{AttachmentDetails, VkObjectRef};
 use quick_xml::events::{attributes::Attributes, Event};
 use quick_xml::Reader;
 use regex::Regex;
@@ -5,17 +6,31 @@ use std::borrow::Cow;
 use std::path::Path;
 
 lazy_static! {
-    static ref USER_MENTION_RE: Regex = Regex::new(r"\[id\d+\|(?P<name>[^\]]+)\]").unwrap();
+    static ref USER_MENTION_RE: Regex = Regex::new(r"\[id(?P<id>\d+)\|(?P<name>[^\]]+)\]").unwrap();
+    static ref AUTHOR_ID_RE: Regex = Regex::new(
+        r"^https?://(?:m\.)?vk\.com/(?P<kind>id|club|public|event)(?P<id>\d+)$"
+    )
+    .unwrap();
 }
 
 #[derive(Debug)]
 pub enum MessageEvent<'a> {
     Start(u32), // > 0 indicates the nesting level for forwarded messages
+    // The number in the id of the message in the dump (id="msg1"); forwarded messages have none
+    MessageIdExtracted(u64),
     FullNameExtracted(&'a str),
+    // Only raised for profile links with a numeric id (vk.com/id1, vk.com/club1), not for
+    // custom addresses; communities have negative ids
+    AuthorIdExtracted(i64),
     ShortNameExtracted(&'a str),
     DateExtracted(&'a str),
     BodyPartExtracted(&'a str),
+    // Raised after the body part containing `[id1|Name]` markup, which is replaced with the name
+    MentionExtracted { id: i64, name: &'a str },
     WallPartExtracted(&'a str),
+    // Attachments of a wall post are enclosed in WallAttachmentsStart and WallAttachmentsEnd
+    WallAttachmentsStart,
+    WallAttachmentsEnd,
     RawAttachmentPartExtracted(&'a str),
     AttachmentExtracted {
         kind: MessageAttachmentKind,
@@ -23,19 +38,162 @@ pub enum MessageEvent<'a> {
         vk_obj: &'a str,
         description: &'a str,
     },
+    DumpEnd, // raised once after the last message, regardless of skipped messages
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+/// An owned copy of a `MessageEvent`, for consumers that need to keep events around
+/// after the reducer returns (see `filter::MessageBuffer`).
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedMessageEvent {
+    Start(u32),
+    MessageIdExtracted(u64),
+    FullNameExtracted(String),
+    AuthorIdExtracted(i64),
+    ShortNameExtracted(String),
+    DateExtracted(String),
+    BodyPartExtracted(String),
+    MentionExtracted { id: i64, name: String },
+    WallPartExtracted(String),
+    WallAttachmentsStart,
+    WallAttachmentsEnd,
+    RawAttachmentPartExtracted(String),
+    AttachmentExtracted {
+        kind: MessageAttachmentKind,
+        url: String,
+        vk_obj: String,
+        description: String,
+    },
+    DumpEnd,
+}
+
+impl OwnedMessageEvent {
+    pub fn as_event(&self) -> MessageEvent<'_> {
+        match self {
+            OwnedMessageEvent::Start(level) => MessageEvent::Start(*level),
+            OwnedMessageEvent::MessageIdExtracted(id) => MessageEvent::MessageIdExtracted(*id),
+            OwnedMessageEvent::FullNameExtracted(s) => MessageEvent::FullNameExtracted(s),
+            OwnedMessageEvent::AuthorIdExtracted(id) => MessageEvent::AuthorIdExtracted(*id),
+            OwnedMessageEvent::ShortNameExtracted(s) => MessageEvent::ShortNameExtracted(s),
+            OwnedMessageEvent::DateExtracted(s) => MessageEvent::DateExtracted(s),
+            OwnedMessageEvent::BodyPartExtracted(s) => MessageEvent::BodyPartExtracted(s),
+            OwnedMessageEvent::MentionExtracted { id, name } => {
+                MessageEvent::MentionExtracted { id: *id, name }
+            }
+            OwnedMessageEvent::WallPartExtracted(s) => MessageEvent::WallPartExtracted(s),
+            OwnedMessageEvent::WallAttachmentsStart => MessageEvent::WallAttachmentsStart,
+            OwnedMessageEvent::WallAttachmentsEnd => MessageEvent::WallAttachmentsEnd,
+            OwnedMessageEvent::RawAttachmentPartExtracted(s) => {
+                MessageEvent::RawAttachmentPartExtracted(s)
+            }
+            OwnedMessageEvent::AttachmentExtracted { kind, url, vk_obj, description } => {
+                MessageEvent::AttachmentExtracted { kind: *kind, url, vk_obj, description }
+            }
+            OwnedMessageEvent::DumpEnd => MessageEvent::DumpEnd,
+        }
+    }
+}
+
+impl<'a> MessageEvent<'a> {
+    pub fn into_owned(self) -> OwnedMessageEvent {
+        match self {
+            MessageEvent::Start(level) => OwnedMessageEvent::Start(level),
+            MessageEvent::MessageIdExtracted(id) => OwnedMessageEvent::MessageIdExtracted(id),
+            MessageEvent::FullNameExtracted(s) => {
+                OwnedMessageEvent::FullNameExtracted(s.to_owned())
+            }
+            MessageEvent::AuthorIdExtracted(id) => OwnedMessageEvent::AuthorIdExtracted(id),
+            MessageEvent::ShortNameExtracted(s) => {
+                OwnedMessageEvent::ShortNameExtracted(s.to_owned())
+            }
+            MessageEvent::DateExtracted(s) => OwnedMessageEvent::DateExtracted(s.to_owned()),
+            MessageEvent::BodyPartExtracted(s) => {
+                OwnedMessageEvent::BodyPartExtracted(s.to_owned())
+            }
+            MessageEvent::MentionExtracted { id, name } => {
+                OwnedMessageEvent::MentionExtracted { id, name: name.to_owned() }
+            }
+            MessageEvent::WallPartExtracted(s) => {
+                OwnedMessageEvent::WallPartExtracted(s.to_owned())
+            }
+            MessageEvent::WallAttachmentsStart => OwnedMessageEvent::WallAttachmentsStart,
+            MessageEvent::WallAttachmentsEnd => OwnedMessageEvent::WallAttachmentsEnd,
+            MessageEvent::RawAttachmentPartExtracted(s) => {
+                OwnedMessageEvent::RawAttachmentPartExtracted(s.to_owned())
+            }
+            MessageEvent::AttachmentExtracted { kind, url, vk_obj, description } => {
+                OwnedMessageEvent::AttachmentExtracted {
+                    kind,
+                    url: url.to_owned(),
+                    vk_obj: vk_obj.to_owned(),
+                    description: description.to_owned(),
+                }
+            }
+            MessageEvent::DumpEnd => OwnedMessageEvent::DumpEnd,
+        }
+    }
+
+    /// Parses the description and url of an `AttachmentExtracted` event, see `AttachmentDetails`.
+    pub fn attachment_details(&self) -> Option<AttachmentDetails<'a>> {
+        match *self {
+            MessageEvent::AttachmentExtracted { kind, url, description, .. } => {
+                AttachmentDetails::parse(kind, url, description)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the `vk_obj` of an `AttachmentExtracted` event, see `VkObjectRef`.
+    pub fn vk_object_ref(&self) -> Option<VkObjectRef> {
+        match *self {
+            MessageEvent::AttachmentExtracted { vk_obj, .. } => VkObjectRef::parse(vk_obj),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
 pub enum MessageAttachmentKind {
     Doc,
     Photo,
     Video,
     Audio,
+    AudioMessage,
     Sticker,
     Location,
     Wall,
 }
 
+impl MessageAttachmentKind {
+    pub const ALL: [MessageAttachmentKind; 8] = [
+        MessageAttachmentKind::Doc,
+        MessageAttachmentKind::Photo,
+        MessageAttachmentKind::Video,
+        MessageAttachmentKind::Audio,
+        MessageAttachmentKind::AudioMessage,
+        MessageAttachmentKind::Sticker,
+        MessageAttachmentKind::Location,
+        MessageAttachmentKind::Wall,
+    ];
+
+    /// A lowercase name suitable for command line options, e.g. `audio_message`.
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageAttachmentKind::Doc => "doc",
+            MessageAttachmentKind::Photo => "photo",
+            MessageAttachmentKind::Video => "video",
+            MessageAttachmentKind::Audio => "audio",
+            MessageAttachmentKind::AudioMessage => "audio_message",
+            MessageAttachmentKind::Sticker => "sticker",
+            MessageAttachmentKind::Location => "location",
+            MessageAttachmentKind::Wall => "wall",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
 pub enum EventResult<A> {
     Consumed(A),
     SkipMessage(A),
@@ -83,6 +241,8 @@ where
     at: ParseState,
     msg_level: u32,
     fwd_closed: bool,
+    wall_depth: Option<u32>,
+    wall_attachments: bool,
     skip_level: Option<u32>,
     acc: A,
     reducer: F,
@@ -138,25 +298,61 @@ where
         at: Prelude,
         msg_level: 0,
         fwd_closed: false,
+        wall_depth: None,
+        wall_attachments: false,
         skip_level: None,
         acc: init,
         reducer,
     };
 
     loop {
-        match reader.read_event(&mut buf) {
+        let event = reader.read_event(&mut buf);
+        // Wall post attachments are nested inside the wall attachment's <div>,
+        // which is only closed after all of them
+        match event {
+            Ok(Event::Start(ref e)) if q!(e, b"div") => {
+                state.wall_depth = state.wall_depth.map(|d| d + 1);
+            }
+            Ok(Event::End(ref e)) if q!(e, b"div") && state.wall_depth == Some(1) => {
+                state.wall_depth = None;
+                if state.wall_attachments {
+                    state.wall_attachments = false;
+                    msg_event!(state, WallAttachmentsEnd);
+                }
+            }
+            Ok(Event::End(ref e)) if q!(e, b"div") => {
+                state.wall_depth = state.wall_depth.map(|d| d - 1);
+            }
+            _ => {}
+        }
+        match event {
             Ok(Event::Start(ref e)) => match state.at {
                 // There's an <hr> tag right before the first msg_item
                 Prelude if q!(e, b"hr") => state.advance(NoMessage),
                 NoMessage | MessageBodyExtracted if q!(e, b"div", b"\"msg_item\"") => {
+                    state.wall_depth = None;
+                    state.wall_attachments = false;
                     state.advance(MessageStart);
                     msg_event!(state, Start(state.msg_level));
+                    // Skipping the message at Start also skips its id
+                    if state.skip_level.is_none() {
+                        if let Some(id) = get_attr(&mut e.attributes(), b"id") {
+                            if let Some(id) = parse_message_id(reader.decode(&id)?) {
+                                msg_event!(state, MessageIdExtracted(id));
+                            }
+                        }
+                    }
                 }
                 MessageStart if q!(e, b"b") => {
                     state.advance(MessageFullNameStart);
                 }
                 MessageFullNameExtracted if q!(e, b"a") => {
                     state.advance(MessageShortNameStart);
+                    if let Some(href) = get_attr(&mut e.attributes(), b"href") {
+                        if let Some(id) = parse_author_id(reader.decode(&href)?) {
+                            msg_event!(state, AuthorIdExtracted(id));
+                        }
+                    }
                 }
                 MessageDateExtracted if q!(e, b"div", b"\"msg_body\"") => {
                     state.advance(MessageBodyStart);
@@ -170,7 +366,11 @@ where
                     state.advance(MessageChatActionStart);
                 }
                 MessageDateExtracted | MessageBodyExtracted if q!(e, b"div", b"\"attacments\"") => {
-                    state.advance(MessageAttachmentsPrelude)
+                    state.advance(MessageAttachmentsPrelude);
+                    if state.wall_depth.is_some() && !state.wall_attachments {
+                        state.wall_attachments = true;
+                        msg_event!(state, WallAttachmentsStart);
+                    }
                 }
                 MessageAttachmentsPrelude | MessageBodyExtracted
                     if q!(e, b"div", b"\"attacment\"") =>
@@ -195,6 +395,10 @@ where
                         b"wall" => MessageAttachmentKind::Wall,
                         _ => panic!("Unsupported attachment container: {:?}", e),
                     };
+                    if kind == MessageAttachmentKind::Wall {
+                        // Both the attachment <div> and the icon <div> are open at this point
+                        state.wall_depth = Some(2);
+                    }
                     state.advance(MessageAttachmentHeadStart(kind));
                 }
                 MessageAttachmentStart if q!(e, b"pre") => {
@@ -237,25 +441,41 @@ where
                 }
                 MessageBodyStart => {
                     let unescaped = &e.unescaped().unwrap_or(Cow::from(e.escaped()));
-                    let text = reader.decode(&unescaped)?;
+                    let text = reader.decode(unescaped)?;
                     if text.contains('[') {
                         let re_text = USER_MENTION_RE.replace_all(text, "$name");
                         msg_event!(state, BodyPartExtracted(&re_text));
+                        for mention in USER_MENTION_RE.captures_iter(text) {
+                            // The id is all digits, so it only fails to parse on overflow
+                            if let Ok(id) = mention["id"].parse() {
+                                let name = mention.name("name").unwrap().as_str();
+                                msg_event!(state, MentionExtracted { id, name });
+                            }
+                        }
                     } else if !text.is_empty() {
-                        msg_event!(state, BodyPartExtracted(&text));
+                        msg_event!(state, BodyPartExtracted(text));
                     }
                 }
                 MessageAttachmentBodyStart(kind, ref url) => {
                     let unescaped = &e.unescaped().unwrap_or(Cow::from(e.escaped()));
-                    let info = reader.decode(&unescaped)?.trim();
-                    let (vk_obj, description) = if info.starts_with('[') {
-                        let mut info_split = info[1..].splitn(2, ']');
+                    let info = reader.decode(unescaped)?.trim();
+                    let (vk_obj, description) = if let Some(info) = info.strip_prefix('[') {
+                        let mut info_split = info.splitn(2, ']');
                         let vk_obj = info_split.next().unwrap_or("");
                         let description = info_split.next().unwrap_or("").trim();
                         (vk_obj, description)
                     } else {
                         ("", info)
                     };
+                    // Voice messages are exported as regular documents
+                    let kind = match description {
+                        "voice_message.webm" | "voice_message.ogg"
+                            if kind == MessageAttachmentKind::Doc =>
+                        {
+                            MessageAttachmentKind::AudioMessage
+                        }
+                        _ => kind,
+                    };
                     msg_event!(
                         state,
                         AttachmentExtracted {
@@ -269,13 +489,13 @@ where
                 }
                 MessageAttachmentRawBodyStart => {
                     let unescaped = &e.unescaped().unwrap_or(Cow::from(e.escaped()));
-                    let data = reader.decode(&unescaped)?;
-                    msg_event!(state, RawAttachmentPartExtracted(&data));
+                    let data = reader.decode(unescaped)?;
+                    msg_event!(state, RawAttachmentPartExtracted(data));
                 }
                 MessageAttachmentWallBodyStart => {
                     let unescaped = &e.unescaped().unwrap_or(Cow::from(e.escaped()));
-                    let text = reader.decode(&unescaped)?;
-                    msg_event!(state, WallPartExtracted(&text));
+                    let text = reader.decode(unescaped)?;
+                    msg_event!(state, WallPartExtracted(text));
                 }
                 _ => (),
             },
@@ -301,20 +521,22 @@ where
                 MessageBodyExtracted if q!(e, b"div") => {
                     state.advance(NoMessage);
                 }
-                NoMessage if q!(e, b"div") => {
-                    if state.msg_level > 0 {
-                        if !state.fwd_closed {
-                            state.fwd_closed = true;
-                        } else {
-                            state.msg_level -= 1;
-                            state.fwd_closed = false;
-                        }
+                NoMessage if q!(e, b"div") && state.msg_level > 0 => {
+                    if !state.fwd_closed {
+                        state.fwd_closed = true;
+                    } else {
+                        state.msg_level -= 1;
+                        state.fwd_closed = false;
                     }
                 }
                 _ => {}
             },
             Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => break,
+            Ok(Event::Eof) => {
+                state.skip_level = None;
+                msg_event!(state, DumpEnd);
+                break;
+            }
             _ => (),
         }
         buf.clear();
@@ -329,6 +551,19 @@ fn get_attr<'a>(attrs: &'a mut Attributes, key: &[u8]) -> Option<Cow<'a, [u8]>>
     })
 }
 
+fn parse_message_id(id: &str) -> Option<u64> {
+    id.strip_prefix("msg")?.parse().ok()
+}
+
+fn parse_author_id(profile_url: &str) -> Option<i64> {
+    let caps = AUTHOR_ID_RE.captures(profile_url)?;
+    let id: i64 = caps["id"].parse().ok()?;
+    match &caps["kind"] {
+        "id" => Some(id),
+        _ => Some(-id),
+    }
+}
+
 // Based on https://stackoverflow.com/a/31102496/1726690
 trait RawText {
     fn trim(&self) -> &Self;
@@ -0,0 +1,113 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+// Inflectional endings of Russian nouns, adjectives and verbs, longest first
+const ENDINGS: &[&str] = &[
+    "ями", "ами", "ого", "его", "ому", "ему", "ыми", "ими", "ешь", "ете", "ишь", "ите", "ла",
+    "ло", "ли", "ть", "ой", "ей", "ом", "ем", "ам", "ям", "ах", "ях", "ую", "юю", "ая", "яя", "ое",
+    "ее", "ые", "ие", "ый", "ий", "ов", "ев", "ет", "ут", "ют", "ит", "им", "ат", "ят", "а", "я",
+    "о", "е", "ы", "и", "у", "ю", "ь", "й", "л",
+];
+
+// Verbs are conjugated without the thematic vowel of the infinitive: бесить, бесит, бесишь
+const INFINITIVE_ENDINGS: &[&str] = &["ить", "еть", "ать", "ять"];
+
+// Stems shorter than this are matched as whole words, so that short words don't match half
+// the dictionary
+const MIN_STEM_CHARS: usize = 3;
+
+/// A list of words to drop or mask, read from a file with one word per line.
+///
+/// Words are matched case-insensitively (with ё read as е) in their common inflected forms:
+/// `дурак` also matches `дурака` and `дураками`, `бесить` matches `бесит`. Endings are told
+/// apart by rules of thumb, so some rare forms are missed and some unrelated words sharing
+/// the stem are matched. A trailing `*` matches any word starting with the prefix (`бля*`).
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wordlist {
+    // Candidate stems of the listed words, and whether the word ends with a soft sign
+    stems: Vec<(String, bool)>,
+    prefixes: Vec<String>,
+}
+
+impl Wordlist {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut wordlist = Wordlist::default();
+        let lines = contents.lines().map(str::trim);
+        for entry in lines.filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match entry.strip_suffix('*') {
+                Some(prefix) => wordlist.prefixes.push(fold(prefix)),
+                None => {
+                    let word = fold(entry);
+                    let soft = word.ends_with('ь');
+                    wordlist.stems.extend(stems(&word).map(|stem| (stem.to_owned(), soft)));
+                }
+            }
+        }
+        wordlist
+    }
+
+    pub fn matches_word(&self, word: &str) -> bool {
+        let word = fold(word);
+        self.prefixes.iter().any(|prefix| word.starts_with(prefix.as_str()))
+            || self.stems.iter().any(|(stem, soft)| {
+                word.strip_prefix(stem.as_str()).is_some_and(|ending| {
+                    // Keeps дура from matching дурь
+                    ending.is_empty() || ((*soft || ending != "ь") && ENDINGS.contains(&ending))
+                })
+            })
+    }
+
+    /// Returns whether any word of the text is listed.
+    pub fn matches(&self, text: &str) -> bool {
+        words(text).any(|(_, word)| self.matches_word(word))
+    }
+
+    /// Replaces each character of the listed words with `*`.
+    pub fn mask<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let mut masked = String::new();
+        let mut copied = 0;
+        for (start, word) in words(text).filter(|(_, word)| self.matches_word(word)) {
+            masked.push_str(&text[copied..start]);
+            masked.extend(word.chars().map(|_| '*'));
+            copied = start + word.len();
+        }
+        if copied == 0 {
+            return Cow::Borrowed(text);
+        }
+        masked.push_str(&text[copied..]);
+        Cow::Owned(masked)
+    }
+}
+
+fn fold(word: &str) -> String {
+    word.to_lowercase().replace('ё', "е")
+}
+
+// The word itself and the stems left by stripping each of the endings it may have
+fn stems(word: &str) -> impl Iterator<Item = &str> {
+    let endings = ENDINGS.iter().chain(INFINITIVE_ENDINGS);
+    let stripped = endings.filter_map(move |ending| word.strip_suffix(ending));
+    std::iter::once(word).chain(stripped.filter(|stem| stem.chars().count() >= MIN_STEM_CHARS))
+}
+
+// Runs of letters and digits with their byte offsets
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                words.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words.into_iter()
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+use vkopt_message_parser::attachment::{AttachmentDetails, Coordinates, VkObjectKind, VkObjectRef};
+use vkopt_message_parser::reader::{fold_html, EventResult, MessageAttachmentKind};
+
+mod test_helper;
+use test_helper::*;
+
+pub fn read_attachment_details(fixture: &str) -> Vec<String> {
+    fold_html(fixture_path(fixture), Vec::new(), |mut vec, event| {
+        if let Some(details) = event.attachment_details() {
+            vec.push(format!("{:?}", details));
+        }
+        EventResult::Consumed(vec)
+    })
+    .unwrap()
+}
+
+#[test]
+fn it_parses_attachment_details() {
+    let details = read_attachment_details("messages_attachments.html");
+    assert_events!(
+        &details,
+        "Video { title: \"Rick Astley - Never Gonna Give You Up (Video)\", duration: Some(213s) }",
+        "AudioMessage { name: \"voice_message.webm\" }",
+        "Audio { artist: Some(\"Johnny Cash\"), title: \"(Ghost) Riders In the Sky\", duration: Some(226s) }",
+        "Photo { width: 640, height: 800 }",
+        "Photo { width: 515, height: 400 }",
+        "Sticker { id: 162, product_id: Some(1), size: Some(256) }",
+        "Wall",
+        "Photo { width: 820, height: 1300 }",
+        "Location { place: \"Osaka, Japan\", coordinates: Some(Coordinates { latitude: 34.691139132132, longitude: 135.50335473102 }) }"
+    );
+}
+
+#[test]
+fn it_parses_media_without_duration() {
+    assert_eq!(
+        AttachmentDetails::parse(MessageAttachmentKind::Audio, "", "Untitled"),
+        Some(AttachmentDetails::Audio { artist: None, title: "Untitled", duration: None })
+    );
+    assert_eq!(
+        AttachmentDetails::parse(MessageAttachmentKind::Video, "", "Stream (1:02:03)"),
+        Some(AttachmentDetails::Video {
+            title: "Stream",
+            duration: Some(Duration::from_secs(3723))
+        })
+    );
+}
+
+#[test]
+fn it_parses_sticker_urls() {
+    assert_eq!(
+        AttachmentDetails::parse(
+            MessageAttachmentKind::Sticker,
+            "https://vk.com/sticker/1-9046-128",
+            ""
+        ),
+        Some(AttachmentDetails::Sticker { id: 9046, product_id: Some(1), size: Some(128) })
+    );
+    assert_eq!(
+        AttachmentDetails::parse(MessageAttachmentKind::Sticker, "", "Sticker #162"),
+        Some(AttachmentDetails::Sticker { id: 162, product_id: None, size: None })
+    );
+}
+
+#[test]
+fn it_rejects_malformed_descriptions() {
+    assert_eq!(AttachmentDetails::parse(MessageAttachmentKind::Photo, "", "photo"), None);
+    assert_eq!(AttachmentDetails::parse(MessageAttachmentKind::Sticker, "", "Sticker"), None);
+    assert_eq!(AttachmentDetails::parse(MessageAttachmentKind::Location, "", "Osaka"), None);
+}
+
+#[test]
+fn it_parses_coordinates_from_maps_urls() {
+    assert_eq!(
+        Coordinates::from_maps_url("https://maps.google.com/maps?hl=ru&q=-33.86,151.2"),
+        Some(Coordinates { latitude: -33.86, longitude: 151.2 })
+    );
+    assert_eq!(
+        Coordinates::from_maps_url("https://maps.google.ru/maps?q=34.69%20135.50"),
+        Some(Coordinates { latitude: 34.69, longitude: 135.50 })
+    );
+    assert_eq!(Coordinates::from_maps_url("https://maps.google.ru/maps?q=Osaka"), None);
+}
+
+#[test]
+fn it_parses_vk_object_refs() {
+    let path = fixture_path("messages_attachments.html");
+    let refs = fold_html(path, Vec::new(), |mut vec, event| {
+        if let Some(vk_ref) = event.vk_object_ref() {
+            vec.push(vk_ref.to_string());
+        }
+        EventResult::Consumed(vec)
+    })
+    .unwrap();
+    // Owner ids are anonymized in the fixture, so wall-0_0 loses its sign
+    assert_events!(
+        &refs,
+        "audio2000015592_456243043",
+        "photo0_0",
+        "photo0_1",
+        "wall0_0",
+        "photo0_0"
+    );
+}
+
+#[test]
+fn it_parses_and_formats_vk_object_refs() {
+    let vk_ref = VkObjectRef::parse("wall-12345_678_a1b2c3").unwrap();
+    assert_eq!(vk_ref, VkObjectRef {
+        kind: VkObjectKind::Wall,
+        owner_id: -12345,
+        item_id: 678,
+        access_key: Some("a1b2c3".to_owned())
+    });
+    assert_eq!(vk_ref.to_string(), "wall-12345_678_a1b2c3");
+    assert_eq!(vk_ref.without_access_key().to_string(), "wall-12345_678");
+    assert_eq!(VkObjectRef::parse("video_00000"), None);
+    assert_eq!(VkObjectRef::parse("photo1"), None);
+    assert_eq!(VkObjectRef::parse("market1_2"), None);
+}
@@ -0,0 +1,175 @@
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod test_helper;
+use test_helper::*;
+
+// Cargo builds examples next to the deps directory holding integration tests
+fn cli_path() -> PathBuf {
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_owned();
+    deps.parent().unwrap().join("examples").join(format!("cli{}", std::env::consts::EXE_SUFFIX))
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vkopt-cli-test-{}", name))
+}
+
+// Runs the CLI with the given arguments, writing to a file named after the test
+fn run_cli(output: &str, args: &[&str], fixture: &str) -> PathBuf {
+    let output = temp_path(output);
+    run_cli_on(&output, args, &fixture_path(fixture));
+    output
+}
+
+fn run_cli_on(output: &Path, args: &[&str], input: &Path) {
+    let status = Command::new(cli_path())
+        .arg("-o")
+        .arg(output)
+        .args(args)
+        .arg("--")
+        .arg(input)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn read_jsonl(path: &PathBuf) -> Vec<Value> {
+    let jsonl = std::fs::read_to_string(path).unwrap();
+    jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+// Lists dates of the messages, with those of forwarded messages in brackets
+fn jsonl_dates(messages: &[Value]) -> String {
+    let dates = messages.iter().map(|message| {
+        let date = message["date"].as_str().unwrap().to_owned();
+        match message["forwarded"].as_array().unwrap().as_slice() {
+            [] => date,
+            forwarded => format!("{} [{}]", date, jsonl_dates(forwarded)),
+        }
+    });
+    dates.collect::<Vec<_>>().join(", ")
+}
+
+#[test]
+fn it_nests_forwarded_messages_in_jsonl() {
+    let args = ["--format", "jsonl", "--forwarded", "all"];
+    let output = run_cli("nested.jsonl", &args, "messages_forwarded_att.html");
+    assert_eq!(
+        jsonl_dates(&read_jsonl(&output)),
+        "2018-01-21T19:00:55, 2018-01-21T19:02:09 [2018-01-21T18:59:35 [2018-01-21T18:58:09]], \
+         2018-01-21T19:36:18"
+    );
+}
+
+#[test]
+fn it_writes_forwarded_messages_of_rejected_messages_as_jsonl_lines() {
+    let args = [
+        "--format",
+        "jsonl",
+        "--forwarded",
+        "matching_authors",
+        "--where",
+        r#"not date="2018.01.21 19:02:09""#,
+    ];
+    let output = run_cli("rejected.jsonl", &args, "messages_forwarded_att.html");
+    assert_eq!(
+        jsonl_dates(&read_jsonl(&output)),
+        "2018-01-21T19:00:55, 2018-01-21T18:59:35 [2018-01-21T18:58:09], 2018-01-21T19:36:18"
+    );
+
+    let args = [
+        "--format",
+        "jsonl",
+        "--forwarded",
+        "matching_authors",
+        "--where",
+        r#"not date="2018.01.21 18:59:35""#,
+    ];
+    let output = run_cli("rejected_parent.jsonl", &args, "messages_forwarded_att.html");
+    assert_eq!(
+        jsonl_dates(&read_jsonl(&output)),
+        "2018-01-21T19:00:55, 2018-01-21T18:58:09, 2018-01-21T19:02:09, 2018-01-21T19:36:18"
+    );
+}
+
+// Reads the cells of a column, skipping the byte order mark and the header
+fn read_csv_column(path: &PathBuf, column: usize) -> Vec<String> {
+    let csv = std::fs::read_to_string(path).unwrap();
+    let mut reader = csv::Reader::from_reader(csv.trim_start_matches('\u{FEFF}').as_bytes());
+    reader.records().map(|record| record.unwrap()[column].to_owned()).collect()
+}
+
+#[test]
+fn it_quotes_multiline_csv_bodies() {
+    let output = run_cli("multiline.csv", &["--format", "csv"], "messages.html");
+    let csv = std::fs::read_to_string(&output).unwrap();
+    assert!(csv.contains("\"Hi Denko\n\nI’m drinking \"\"jasmine\"\" tea right now,"));
+    assert_eq!(
+        read_csv_column(&output, 4)[1],
+        "Hi Denko\n\nI’m drinking \"jasmine\" tea right now, thinking about what to have for \
+         dinner (´･ω･`)"
+    );
+}
+
+#[test]
+fn it_escapes_csv_formulas() {
+    let output = run_cli("formulas.csv", &["--format", "csv"], "messages_formulas.html");
+    assert_events!(
+        read_csv_column(&output, 4),
+        "'=HYPERLINK(\"https://vk.com\", \"vk\")",
+        "'+1",
+        "'-1 from me",
+        "'@denko look",
+        "2 + 2 = 4"
+    );
+}
+
+// Counts users, messages, attachments and forwards
+fn sqlite_counts(db: &Path) -> [i64; 4] {
+    let db = rusqlite::Connection::open(db).unwrap();
+    ["users", "messages", "attachments", "forwards"].map(|table| {
+        let query = format!("SELECT count(*) FROM {}", table);
+        db.query_row(&query, [], |row| row.get(0)).unwrap()
+    })
+}
+
+#[test]
+fn it_imports_dumps_into_sqlite_once() {
+    let fixtures = std::fs::read_dir(fixture_path("")).unwrap().map(|entry| entry.unwrap().path());
+    for fixture in fixtures.filter(|path| path.extension().unwrap() == "html") {
+        let db = temp_path(&format!("{}.db", fixture.file_stem().unwrap().to_str().unwrap()));
+        let _ = std::fs::remove_file(&db);
+        run_cli_on(&db, &["--format", "sqlite", "--forwarded", "all"], &fixture);
+        let counts = sqlite_counts(&db);
+        assert!(counts[1] > 0, "{:?}", fixture);
+        run_cli_on(&db, &["--format", "sqlite", "--forwarded", "all"], &fixture);
+        assert_eq!(sqlite_counts(&db), counts, "{:?}", fixture);
+    }
+
+    // Messages with repeated ids (msg0) are imported separately
+    let db = temp_path("messages.db");
+    assert_eq!(sqlite_counts(&db), [2, 5, 1, 0]);
+    let db = temp_path("messages_forwarded_att.db");
+    assert_eq!(sqlite_counts(&db), [2, 5, 2, 2]);
+}
+
+#[test]
+fn it_keeps_messages_of_other_dumps_in_sqlite() {
+    let db = temp_path("edited.db");
+    let _ = std::fs::remove_file(&db);
+    let original = fixture_path("messages_attachments.html");
+    let edited = temp_path("messages_attachments_edited.html");
+    let dump = std::fs::read_to_string(&original).unwrap();
+    std::fs::write(&edited, dump.replace("wish you were here", "wish I were there")).unwrap();
+    run_cli_on(&db, &["--format", "sqlite"], &original);
+    let [users, messages, attachments, _] = sqlite_counts(&db);
+    run_cli_on(&db, &["--format", "sqlite"], &edited);
+    assert_eq!(sqlite_counts(&db), [users, messages * 2, attachments * 2, 0]);
+
+    let db = rusqlite::Connection::open(db).unwrap();
+    let mut query = db.prepare("SELECT body FROM messages WHERE msg_id = 6 ORDER BY id").unwrap();
+    let bodies = query.query_map([], |row| row.get::<_, String>(0)).unwrap();
+    let bodies: Vec<_> = bodies.map(Result::unwrap).collect();
+    assert_events!(bodies, "wish you were here", "wish I were there");
+}
@@ -0,0 +1,105 @@
+use vkopt_message_parser::config::{ConfigError, FilterConfig};
+use vkopt_message_parser::filter::{Filter, ForwardedPolicy};
+
+mod test_helper;
+use test_helper::*;
+
+// The filter does not borrow from the config, so it can be kept around
+fn load_filter(config: &str) -> Filter<'static> {
+    FilterConfig::from_toml(config).unwrap().into_filter().unwrap()
+}
+
+#[test]
+fn it_loads_filters_from_files() {
+    let filter = FilterConfig::load(fixture_path("filter.toml")).unwrap().into_filter().unwrap();
+    assert_eq!(filter.forwarded, ForwardedPolicy::MatchingAuthors);
+    let dates = read_dates("messages_forwarded.html", &filter);
+    assert_events!(&dates, "2019.01.02 07:02:58", "2019.01.02 07:03:04");
+}
+
+#[test]
+fn it_reads_toml_and_json() {
+    let toml = FilterConfig::from_toml(
+        r#"
+        full_name_blacklist = ["Sota"]
+        max_forwarded_depth = 1
+        weekdays = ["sun"]
+        query = "not has:photo"
+        "#,
+    )
+    .unwrap();
+    let json = FilterConfig::from_json(
+        r#"{
+            "full_name_blacklist": ["Sota"],
+            "max_forwarded_depth": 1,
+            "weekdays": ["sun"],
+            "query": "not has:photo"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(toml, json);
+
+    let dates = read_dates("messages.html", &toml.into_filter().unwrap());
+    assert_events!(&dates, "2018.01.21 17:02:54");
+}
+
+#[test]
+fn it_accepts_cli_forwarded_values() {
+    let policy = |name| load_filter(&format!("forwarded = \"{}\"", name)).forwarded;
+    assert_eq!(policy("none"), ForwardedPolicy::Exclude);
+    assert_eq!(policy("all"), ForwardedPolicy::Include);
+    assert_eq!(policy("matching_authors"), ForwardedPolicy::MatchingAuthors);
+    for name in &ForwardedPolicy::NAMES {
+        assert_eq!(Some(policy(*name)), ForwardedPolicy::from_name(name));
+    }
+}
+
+#[test]
+fn it_builds_owned_filters() {
+    let filter = load_filter("short_name_blacklist = [\"sota\"]\nmax_body_length = 3");
+    let dates = read_dates("messages.html", &filter);
+    assert_events!(&dates, "2018.01.21 17:02:54");
+}
+
+#[test]
+fn it_reports_invalid_configs() {
+    let error = |config| FilterConfig::from_toml(config).and_then(|c| c.into_filter()).err();
+    let invalid_value = |config| match error(config) {
+        Some(ConfigError::InvalidValue { field, message }) => format!("{}: {}", field, message),
+        e => panic!("expected an invalid value, got {:?}", e),
+    };
+    assert!(matches!(error("sort = \"date\""), Some(ConfigError::Toml(_))));
+    assert!(matches!(error("since_date = 2019"), Some(ConfigError::Toml(_))));
+    assert_eq!(invalid_value("forwarded = \"include\""), "forwarded: include");
+    assert_eq!(
+        invalid_value("forwarded = \"exclude\"\nmax_forwarded_depth = 1"),
+        "max_forwarded_depth: conflicts with forwarded"
+    );
+    assert_eq!(
+        invalid_value("attachment_kind_whitelist = [\"gif\"]"),
+        "attachment_kind_whitelist: gif"
+    );
+    assert_eq!(invalid_value("weekdays = [\"someday\"]"), "weekdays: someday");
+    assert_eq!(invalid_value("time_of_day = \"9-18\""), "time_of_day: 9-18");
+    assert_eq!(
+        invalid_value("query = \"has:gif\""),
+        "query: unknown attachment kind (at position 4)"
+    );
+    assert_eq!(
+        invalid_value("dedup_scope = \"per_author\""),
+        "dedup_window: is required by dedup_text and dedup_scope"
+    );
+    assert_eq!(invalid_value("mask_wordlist = true"), "wordlist: is required by mask_wordlist");
+    assert_eq!(
+        invalid_value("timezone = \"Asia/Tokyo\""),
+        "dump_timezone: is required by timezone"
+    );
+    assert_eq!(invalid_value("near_duplicate_threshold = 1.5"), "near_duplicate_threshold: 1.5");
+    assert_eq!(invalid_value("sample_fraction = -0.5"), "sample_fraction: -0.5");
+    assert_eq!(invalid_value("sample_fraction = nan"), "sample_fraction: NaN");
+    assert!(invalid_value("since_date = \"2019.01.01\"").starts_with("since_date: 2019.01.01"));
+    assert!(matches!(
+        FilterConfig::load(fixture_path("missing.toml")),
+        Err(ConfigError::Io(_))
+    ));
+}
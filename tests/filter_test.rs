@@ -1,4 +1,13 @@
-use vkopt_message_parser::filter::Filter;
+use chrono::{NaiveTime, Weekday};
+use regex::Regex;
+use std::ops::Bound;
+use vkopt_message_parser::filter::{
+    parse_date, BufferedMessage, ContentClass, Dedup, DedupScope, DedupText, Filter,
+    ForwardedPolicy, LengthUnit, MessageBuffer, Sample,
+};
+use vkopt_message_parser::reader::{
+    fold_html, EventResult, MessageAttachmentKind, MessageEvent, OwnedMessageEvent,
+};
 
 mod test_helper;
 use test_helper::*;
@@ -7,6 +16,33 @@ pub fn read_events_filtered(fixture: &str, filter: Filter) -> Vec<String> {
     read_events_skipping(fixture, |e| filter.filter_event(e).is_some())
 }
 
+pub fn read_events_buffered(fixture: &str, filter: Filter) -> Vec<String> {
+    let mut buffer = MessageBuffer::new(&filter);
+    fold_html(fixture_path(fixture), Vec::new(), |mut vec, event| {
+        if let Some(message) = buffer.push(event) {
+            vec.extend(message.events.iter().map(|e| format!("{:?}", e)));
+        }
+        EventResult::Consumed(vec)
+    })
+    .unwrap()
+}
+
+pub fn read_events_wrapped<P: Fn(MessageEvent) -> bool>(
+    fixture: &str,
+    filter: Filter,
+    pred: P,
+) -> Vec<String> {
+    let reducer = filter.wrap(|mut vec: Vec<String>, event| {
+        vec.push(format!("{:?}", event));
+        if pred(event) {
+            EventResult::Consumed(vec)
+        } else {
+            EventResult::SkipMessage(vec)
+        }
+    });
+    fold_html(fixture_path(fixture), Vec::new(), reducer).unwrap()
+}
+
 #[test]
 fn it_does_not_apply_filters_by_default() {
     let filtered = read_events_filtered("messages.html", Default::default());
@@ -25,34 +61,187 @@ fn it_filters_by_min_date() {
     let filtered = read_events_filtered("messages.html", filter);
     assert_events!(&filtered,
         "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 11:05:13\")",
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "Start(0)",
+        "MessageIdExtracted(2)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.22 10:03:04\")",
+        "AttachmentExtracted { kind: Photo, url: \"photo\", vk_obj: \"photo420_13\", description: \"(420x6)\" }",
+        "Start(0)",
+        "MessageIdExtracted(3)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.22 10:05:13\")",
+        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here's my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")",
+        "DumpEnd"
+    );
+}
+
+#[test]
+fn it_filters_by_max_date() {
+    let filter = Filter {
+        until_date: Some(parse_date("2018.01.21 13:53:59").unwrap()),
+        ..Default::default()
+    };
+    let filtered = read_events_filtered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 11:05:13\")",
+        "BodyPartExtracted(\"Hi Denko\")",
+        "MentionExtracted { id: 2342343, name: \"Denko\" }",
+        "BodyPartExtracted(\"\\n\")",
+        "BodyPartExtracted(\"\\n\")",
+        "BodyPartExtracted(\"I’m drinking \\\"jasmine\\\" tea right now, thinking about what to have for dinner (´･ω･`)\")",
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "Start(0)",
+        "MessageIdExtracted(2)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.22 10:03:04\")",
+        "Start(0)",
+        "MessageIdExtracted(3)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.22 10:05:13\")",
+        "DumpEnd"
+    );
+}
+
+#[test]
+fn it_filters_by_date_range_with_exclusive_bounds() {
+    let filter = Filter {
+        date_range: Some((
+            Bound::Excluded(parse_date("2018.01.21 11:05:13").unwrap()),
+            Bound::Excluded(parse_date("2018.01.22 10:05:13").unwrap()),
+        )),
+        ..Default::default()
+    };
+    let filtered = read_events_filtered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 11:05:13\")",
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 17:02:54\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")",
         "Start(0)",
+        "MessageIdExtracted(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:03:04\")",
         "AttachmentExtracted { kind: Photo, url: \"photo\", vk_obj: \"photo420_13\", description: \"(420x6)\" }",
         "Start(0)",
+        "MessageIdExtracted(3)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:05:13\")",
-        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here\\\'s my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")"
+        "DumpEnd"
+    );
+}
+
+fn dates_of(events: Vec<String>) -> Vec<String> {
+    events.into_iter().filter(|e| e.starts_with("DateExtracted")).collect()
+}
+
+#[test]
+fn it_filters_by_weekday_and_time_of_day() {
+    let time = |t| NaiveTime::parse_from_str(t, "%H:%M").unwrap();
+    let filter = Filter {
+        weekdays: Some([Weekday::Sat, Weekday::Sun].iter().cloned().collect()),
+        time_of_day: Some((time("11:00"), time("14:00"))),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered,
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "DateExtracted(\"2018.01.21 11:05:13\")"
+    );
+
+    let filter = Filter {
+        time_of_day: Some((time("17:00"), time("10:04"))),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered,
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "DateExtracted(\"2018.01.22 10:03:04\")"
     );
 }
 
+#[test]
+fn it_converts_dates_to_timezone() {
+    // 2018.01.21 17:02:54 in Moscow is 2018.01.21 23:02:54 in Tokyo, 2018.01.22 10:0x are 16:0x
+    let filter = Filter {
+        weekdays: Some([Weekday::Sun].iter().cloned().collect()),
+        time_of_day: Some((
+            NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+        )),
+        dump_timezone: Some(chrono_tz::Europe::Moscow),
+        timezone: Some(chrono_tz::Asia::Tokyo),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 17:02:54\")");
+}
+
 #[test]
 fn it_filters_by_short_name_blacklist() {
     let mut blacklist = std::collections::BTreeSet::new();
-    blacklist.insert("sota");
+    blacklist.insert("sota".into());
     let filter = Filter {
         short_name_blacklist: Some(blacklist),
         ..Default::default()
@@ -60,31 +249,42 @@ fn it_filters_by_short_name_blacklist() {
     let filtered = read_events_filtered("messages.html", filter);
     assert_events!(&filtered,
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 17:02:54\")",
         "BodyPartExtracted(\"🤔\")",
         "BodyPartExtracted(\"🤔\")",
         "BodyPartExtracted(\"🤔\")",
         "Start(0)",
+        "MessageIdExtracted(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "Start(0)",
+        "MessageIdExtracted(3)",
         "FullNameExtracted(\"Sota\")",
-        "ShortNameExtracted(\"sota\")"
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DumpEnd"
     );
 }
 
 #[test]
 fn it_filters_by_short_name_whitelist() {
     let mut whitelist = std::collections::BTreeSet::new();
-    whitelist.insert("sota");
+    whitelist.insert("sota".into());
     let filter = Filter {
         short_name_whitelist: Some(whitelist),
         ..Default::default()
@@ -92,29 +292,589 @@ fn it_filters_by_short_name_whitelist() {
     let filtered = read_events_filtered("messages.html", filter);
     assert_events!(&filtered,
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 11:05:13\")",
         "BodyPartExtracted(\"Hi Denko\")",
+        "MentionExtracted { id: 2342343, name: \"Denko\" }",
         "BodyPartExtracted(\"\\n\")",
         "BodyPartExtracted(\"\\n\")",
         "BodyPartExtracted(\"I’m drinking \\\"jasmine\\\" tea right now, thinking about what to have for dinner (´･ω･`)\")",
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "Start(0)",
+        "MessageIdExtracted(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:03:04\")",
         "AttachmentExtracted { kind: Photo, url: \"photo\", vk_obj: \"photo420_13\", description: \"(420x6)\" }",
         "Start(0)",
+        "MessageIdExtracted(3)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.22 10:05:13\")",
+        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here's my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")",
+        "DumpEnd"
+    );
+}
+
+#[test]
+fn it_applies_both_name_whitelist_and_blacklist() {
+    let names = |ns: &[&'static str]| Some(ns.iter().map(|&n| n.into()).collect());
+    let filter = Filter {
+        short_name_whitelist: names(&["sota", "denko"]),
+        short_name_blacklist: names(&["sota"]),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")"
+    );
+}
+
+#[test]
+fn it_filters_by_author_id() {
+    let filter = Filter {
+        forwarded: ForwardedPolicy::MatchingAuthors,
+        author_id_whitelist: Some([2].iter().cloned().collect()),
+        ..Default::default()
+    };
+    // Forwarded messages of vk.com/denko carry no numeric id
+    let filtered = dates_of(read_events_buffered("messages_forwarded.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2019.01.02 07:03:18\")");
+
+    let filter = Filter {
+        forwarded: ForwardedPolicy::MatchingAuthors,
+        author_id_blacklist: Some([1].iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages_forwarded.html", filter));
+    assert_events!(&filtered,
+        "DateExtracted(\"2019.01.02 07:03:18\")",
+        "DateExtracted(\"2018.01.21 20:48:07\")",
+        "DateExtracted(\"2018.01.21 20:48:10\")",
+        "DateExtracted(\"2019.01.02 07:03:06\")"
+    );
+}
+
+#[test]
+fn it_filters_events_by_author_id() {
+    let filter = Filter {
+        author_id_blacklist: Some([1].iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = read_events_filtered("messages.html", filter);
+    assert_events!(&filtered[..10],
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "Start(0)",
+        "MessageIdExtracted(1)"
+    );
+}
+
+#[test]
+fn it_filters_by_mentioned_users() {
+    let filter = Filter {
+        mention_id_whitelist: Some([2342343].iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 11:05:13\")");
+
+    let filter = Filter {
+        mention_id_whitelist: Some([2].iter().cloned().collect()),
+        mention_name_whitelist: Some(["Denko".into()].iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 11:05:13\")");
+
+    let filter = Filter {
+        mention_name_whitelist: Some(["Sota".into()].iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert!(filtered.is_empty());
+}
+
+#[test]
+fn it_matches_names_with_globs_ignoring_case() {
+    let names = |ns: &[&'static str]| Some(ns.iter().map(|&n| n.into()).collect());
+    let filter = Filter {
+        short_name_whitelist: names(&["D*"]),
+        name_globs: true,
+        ..Default::default()
+    };
+    assert!(dates_of(read_events_buffered("messages.html", filter)).is_empty());
+
+    let filter = Filter {
+        short_name_whitelist: names(&["D*"]),
+        name_globs: true,
+        name_case_insensitive: true,
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 17:02:54\")");
+
+    let filter = Filter {
+        full_name_blacklist: names(&["s?TA", "*x*"]),
+        name_globs: true,
+        name_case_insensitive: true,
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 17:02:54\")");
+
+    // Without globs, wildcards are matched literally
+    let filter = Filter {
+        short_name_whitelist: names(&["*", "SOTA"]),
+        name_case_insensitive: true,
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_eq!(filtered.len(), 4);
+
+    let filter = Filter {
+        short_name_whitelist: names(&["*k*o", "?"]),
+        name_globs: true,
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 17:02:54\")");
+}
+
+#[test]
+fn it_filters_by_full_name_whitelist() {
+    let mut whitelist = std::collections::BTreeSet::new();
+    whitelist.insert("Denko".into());
+    let filter = Filter {
+        full_name_whitelist: Some(whitelist),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")",
+        "BodyPartExtracted(\"🤔\")"
+    );
+}
+
+#[test]
+fn it_filters_by_body_whitelist() {
+    let filter = Filter {
+        body_whitelist: Some(Regex::new(r"(?i)hi denko").unwrap()),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(0)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 11:05:13\")",
+        "BodyPartExtracted(\"Hi Denko\")",
+        "MentionExtracted { id: 2342343, name: \"Denko\" }",
+        "BodyPartExtracted(\"\\n\")",
+        "BodyPartExtracted(\"\\n\")",
+        "BodyPartExtracted(\"I’m drinking \\\"jasmine\\\" tea right now, thinking about what to have for dinner (´･ω･`)\")"
+    );
+}
+
+#[test]
+fn it_filters_by_body_blacklist_with_forwarded_messages() {
+    let filter = Filter {
+        body_blacklist: Some(Regex::new(r"how about now").unwrap()),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages_forwarded.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:18\")",
+        "BodyPartExtracted(\"take it and leave\")",
+        "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:02:58\")",
+        "BodyPartExtracted(\"pwetty pwease\")",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:03:04\")",
+        "BodyPartExtracted(\"pwease don't ignore me (´･ω･`)\")",
+        "Start(1)",
+        "FullNameExtracted(\"Denko\")",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:06\")",
+        "BodyPartExtracted(\"tomorrow maybe\")"
+    );
+}
+
+#[test]
+fn it_filters_by_attachment_kinds_ignoring_wall_attachments() {
+    let mut whitelist = std::collections::BTreeSet::new();
+    whitelist.insert(MessageAttachmentKind::Photo);
+    let filter = Filter {
+        attachment_kind_whitelist: Some(whitelist),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages_attachments.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(3)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "AttachmentExtracted { kind: Photo, url: \"https://userapi.com/0.jpg\", vk_obj: \"photo0_0\", description: \"(640x800)\" }",
+        "AttachmentExtracted { kind: Photo, url: \"https://userapi.com/1.jpg\", vk_obj: \"photo0_1\", description: \"(515x400)\" }"
+    );
+}
+
+#[test]
+fn it_filters_out_attachment_only_messages() {
+    let filter = Filter {
+        attachment_only_blacklist: Some(MessageAttachmentKind::ALL.iter().copied().collect()),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages_attachments.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(6)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "BodyPartExtracted(\"wish you were here\")",
+        "AttachmentExtracted { kind: Location, url: \"https://maps.google.ru/maps?q=34.691139132132 135.50335473102\", vk_obj: \"\", description: \"Location: Osaka, Japan\" }"
+    );
+}
+
+#[test]
+fn it_filters_by_links() {
+    let filter = Filter {
+        has_links: Some(true),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(3)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:05:13\")",
-        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here\\\'s my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")"
+        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here's my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")"
+    );
+}
+
+#[test]
+fn it_filters_by_body_length() {
+    let filter = Filter {
+        min_body_length: Some(17),
+        max_body_length: Some(18),
+        body_length_unit: LengthUnit::Words,
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered,
+        "DateExtracted(\"2018.01.22 10:05:13\")"
     );
+
+    let filter = Filter { max_body_length: Some(3), ..Default::default() };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered,
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "DateExtracted(\"2018.01.21 17:02:54\")",
+        "DateExtracted(\"2018.01.22 10:03:04\")"
+    );
+}
+
+#[test]
+fn it_filters_by_content_class() {
+    let filter = Filter {
+        content_class_blacklist: Some(ContentClass::ALL.iter().cloned().collect()),
+        ..Default::default()
+    };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    // The first message is a chat action without text or attachments
+    assert_events!(&filtered,
+        "DateExtracted(\"2018.01.21 13:53:59\")",
+        "DateExtracted(\"2018.01.21 11:05:13\")",
+        "DateExtracted(\"2018.01.22 10:05:13\")"
+    );
+
+    let mut whitelist = std::collections::BTreeSet::new();
+    whitelist.insert(ContentClass::EmojiOnly);
+    let filter = Filter { content_class_whitelist: Some(whitelist), ..Default::default() };
+    let filtered = dates_of(read_events_buffered("messages.html", filter));
+    assert_events!(&filtered, "DateExtracted(\"2018.01.21 17:02:54\")");
+}
+
+#[test]
+fn it_classifies_message_content() {
+    let classes_of = |body: &str| {
+        let message = BufferedMessage {
+            level: 0,
+            events: vec![
+                OwnedMessageEvent::Start(0),
+                OwnedMessageEvent::BodyPartExtracted(body.to_owned()),
+            ],
+        };
+        message.content_classes()
+    };
+    assert_eq!(classes_of(" 👍🏻 🇯🇵\n❤️"), vec![ContentClass::EmojiOnly]);
+    assert_eq!(classes_of("https://t.co/a www.example.com"), vec![ContentClass::LinkOnly]);
+    assert_eq!(classes_of("/roll 1d6"), vec![ContentClass::BotCommand]);
+    assert_eq!(classes_of("!help"), vec![ContentClass::BotCommand]);
+    assert_eq!(classes_of("see https://t.co/a"), vec![]);
+    assert_eq!(classes_of("1/2 done!"), vec![]);
+}
+
+#[test]
+fn it_excludes_forwarded_messages() {
+    let filter = Filter {
+        forwarded: ForwardedPolicy::Exclude,
+        ..Default::default()
+    };
+    let filtered = read_events_filtered("messages_forwarded.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:18\")",
+        "BodyPartExtracted(\"take it and leave\")",
+        "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
+        "Start(1)",
+        "Start(1)",
+        "Start(1)",
+        "DumpEnd"
+    );
+}
+
+#[test]
+fn it_filters_by_forwarded_message_depth() {
+    let filter = Filter {
+        forwarded: ForwardedPolicy::MaxDepth(1),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages_forwarded.html", filter);
+    assert_events!(&filtered,
+        "Start(0)",
+        "MessageIdExtracted(1)",
+        "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:18\")",
+        "BodyPartExtracted(\"take it and leave\")",
+        "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:02:58\")",
+        "BodyPartExtracted(\"pwetty pwease\")",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:03:04\")",
+        "BodyPartExtracted(\"pwease don't ignore me (´･ω･`)\")",
+        "Start(1)",
+        "FullNameExtracted(\"Denko\")",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:06\")",
+        "BodyPartExtracted(\"tomorrow maybe\")"
+    );
+}
+
+#[test]
+fn it_includes_forwarded_messages_of_matching_authors() {
+    let mut whitelist = std::collections::BTreeSet::new();
+    whitelist.insert("sota".into());
+    let filter = Filter {
+        forwarded: ForwardedPolicy::MatchingAuthors,
+        short_name_whitelist: Some(whitelist),
+        ..Default::default()
+    };
+    let filtered = read_events_buffered("messages_forwarded.html", filter);
+    assert_events!(&filtered,
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:02:58\")",
+        "BodyPartExtracted(\"pwetty pwease\")",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:03:04\")",
+        "BodyPartExtracted(\"pwease don't ignore me (´･ω･`)\")",
+        "Start(2)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2018.01.21 20:48:19\")",
+        "BodyPartExtracted(\"how about now? (´･ω･`)\")"
+    );
+}
+
+#[test]
+fn it_wraps_reducers() {
+    let filters = || {
+        let mut whitelist = std::collections::BTreeSet::new();
+        whitelist.insert("sota".into());
+        vec![
+            Filter::default(),
+            Filter { forwarded: ForwardedPolicy::Exclude, ..Default::default() },
+            Filter {
+                forwarded: ForwardedPolicy::MatchingAuthors,
+                short_name_whitelist: Some(whitelist),
+                ..Default::default()
+            },
+            Filter { body_blacklist: Some(Regex::new("pwease").unwrap()), ..Default::default() },
+        ]
+    };
+    for (filter, buffered_filter) in filters().into_iter().zip(filters()) {
+        let wrapped = read_events_wrapped("messages_forwarded.html", filter, |_| true);
+        let mut buffered = read_events_buffered("messages_forwarded.html", buffered_filter);
+        buffered.push("DumpEnd".to_owned());
+        assert_eq!(wrapped, buffered);
+    }
+}
+
+#[test]
+fn it_skips_messages_in_wrapped_reducers() {
+    fn pred(e: MessageEvent) -> bool {
+        !matches!(e, MessageEvent::DateExtracted("2019.01.02 07:03:04"))
+    }
+    let wrapped = read_events_wrapped("messages_forwarded.html", Filter::default(), pred);
+    assert_eq!(wrapped, read_events_skipping("messages_forwarded.html", pred));
+    assert_events!(&wrapped[13..],
+        "BodyPartExtracted(\"pwetty pwease\")",
+        "Start(1)",
+        "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
+        "ShortNameExtracted(\"sota\")",
+        "DateExtracted(\"2019.01.02 07:03:04\")",
+        "Start(1)",
+        "FullNameExtracted(\"Denko\")",
+        "ShortNameExtracted(\"denko\")",
+        "DateExtracted(\"2019.01.02 07:03:06\")",
+        "BodyPartExtracted(\"tomorrow maybe\")",
+        "DumpEnd"
+    );
+}
+
+// Returns the dates of the messages that pass the filter, reading the fixtures in order
+fn read_dates_sampled(fixtures: &[&str], filter: &Filter) -> Vec<String> {
+    fixtures
+        .iter()
+        .flat_map(|fixture| read_dates(fixture, filter))
+        .collect()
+}
+
+#[test]
+fn it_drops_repeated_messages() {
+    let read_deduped = |window, text, scope| {
+        let filter = Filter { dedup: Some(Dedup::new(window, text, scope)), ..Default::default() };
+        dates_of(read_events_buffered("messages_repeated.html", filter))
+    };
+    let deduped = read_deduped(10, DedupText::Exact, DedupScope::Global);
+    assert_events!(&deduped,
+        "DateExtracted(\"2019.02.01 12:00:00\")",
+        "DateExtracted(\"2019.02.01 12:00:10\")",
+        "DateExtracted(\"2019.02.01 12:00:15\")",
+        "DateExtracted(\"2019.02.01 12:00:25\")"
+    );
+
+    // Emoji-only texts are compared as they are
+    let deduped = read_deduped(10, DedupText::Normalized, DedupScope::PerAuthor);
+    assert_events!(&deduped,
+        "DateExtracted(\"2019.02.01 12:00:00\")",
+        "DateExtracted(\"2019.02.01 12:00:05\")",
+        "DateExtracted(\"2019.02.01 12:00:15\")",
+        "DateExtracted(\"2019.02.01 12:00:25\")"
+    );
+
+    // The last message only repeats the first one, which is out of the window by then
+    let deduped = read_deduped(2, DedupText::Exact, DedupScope::Global);
+    assert_events!(&deduped,
+        "DateExtracted(\"2019.02.01 12:00:00\")",
+        "DateExtracted(\"2019.02.01 12:00:10\")",
+        "DateExtracted(\"2019.02.01 12:00:15\")",
+        "DateExtracted(\"2019.02.01 12:00:25\")",
+        "DateExtracted(\"2019.02.01 12:00:30\")"
+    );
+}
+
+#[test]
+fn it_samples_every_nth_message_across_files() {
+    let filter = Filter { sample: Some(Sample::every_nth(3)), ..Default::default() };
+    let sampled = read_dates_sampled(&["messages.html", "messages_forwarded.html"], &filter);
+    // Skipping 2019.01.02 07:03:04 also skips the messages forwarded in it
+    assert_events!(&sampled, "2018.01.21 17:02:54", "2019.01.02 07:03:18", "2019.01.02 07:03:06");
+}
+
+#[test]
+fn it_samples_fractions_independently_of_file_order() {
+    let fixtures = ["messages.html", "messages_forwarded.html"];
+    let sample = |fixtures: &[&str], fraction, seed| {
+        let filter =
+            Filter { sample: Some(Sample::fraction(fraction, seed)), ..Default::default() };
+        let mut dates = read_dates_sampled(fixtures, &filter);
+        dates.sort();
+        dates
+    };
+    let all = sample(&fixtures, 1.0, 0);
+    assert_eq!(all.len(), 12);
+    assert_eq!(sample(&fixtures, 0.0, 0), Vec::<String>::new());
+
+    let half = sample(&fixtures, 0.5, 1);
+    assert!(!half.is_empty() && half.len() < all.len());
+    assert_eq!(half, sample(&["messages_forwarded.html", "messages.html"], 0.5, 1));
+    assert_ne!(half, sample(&fixtures, 0.5, 2));
 }
@@ -0,0 +1,63 @@
+use vkopt_message_parser::filter::Filter;
+use vkopt_message_parser::near_duplicates::{ClusterMember, NearDuplicates};
+
+mod test_helper;
+use test_helper::*;
+
+fn member(short_name: &str, date: &str, body: &str) -> ClusterMember {
+    ClusterMember { short_name: short_name.to_owned(), date: date.to_owned(), body: body.to_owned() }
+}
+
+#[test]
+fn it_reports_clusters_of_near_duplicates() {
+    let filter = Filter {
+        near_duplicates: Some(NearDuplicates::new(0.7, false)),
+        ..Default::default()
+    };
+    let dates = read_dates("messages_near_duplicates.html", &filter);
+    assert_eq!(dates.len(), 6);
+
+    let clusters = filter.near_duplicates.unwrap().clusters();
+    assert_eq!(clusters, vec![vec![
+        member(
+            "sota",
+            "2019.03.01 09:00:00",
+            "I'd just like to interject for a moment. What you're referring to as Linux is in fact GNU/Linux"
+        ),
+        member(
+            "denko",
+            "2019.03.01 09:01:00",
+            "I'd just like to interject for a moment. What you are referring to as Linux is in fact GNU+Linux"
+        ),
+        member(
+            "denko",
+            "2019.03.01 09:03:00",
+            "i'd just like to interject for a moment, what you're referring to as linux, is in fact, GNU/Linux!!!"
+        ),
+    ]]);
+}
+
+#[test]
+fn it_drops_near_duplicates() {
+    let filter = Filter {
+        near_duplicates: Some(NearDuplicates::new(0.7, true)),
+        ..Default::default()
+    };
+    let dates = read_dates("messages_near_duplicates.html", &filter);
+    assert_events!(
+        &dates,
+        "2019.03.01 09:00:00",
+        "2019.03.01 09:02:00",
+        "2019.03.01 09:03:30",
+        "2019.03.01 09:04:00"
+    );
+
+    // Only the exact repeat (ignoring case and punctuation) is similar enough
+    let filter = Filter {
+        near_duplicates: Some(NearDuplicates::new(0.99, true)),
+        ..Default::default()
+    };
+    let dates = read_dates("messages_near_duplicates.html", &filter);
+    assert_eq!(dates.len(), 5);
+    assert!(!dates.contains(&"2019.03.01 09:03:00".to_owned()));
+}
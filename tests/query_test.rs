@@ -0,0 +1,75 @@
+use vkopt_message_parser::filter::{Filter, ForwardedPolicy};
+use vkopt_message_parser::query::{Predicate, QueryError};
+
+mod test_helper;
+use test_helper::*;
+
+// Returns the level, the short name and the date of each matching message
+pub fn read_matching(fixture: &str, query: &str) -> Vec<String> {
+    let filter = Filter {
+        forwarded: ForwardedPolicy::MatchingAuthors,
+        predicate: Some(Predicate::parse(query).unwrap()),
+        ..Default::default()
+    };
+    read_messages(fixture, &filter, |message| {
+        let short_name = message.short_name().unwrap_or("");
+        let date = message.date().unwrap_or("");
+        format!("{} {} {}", message.level, short_name, date)
+    })
+}
+
+#[test]
+fn it_combines_terms() {
+    let matching =
+        read_matching("messages.html", "author:sota and date>=2018.01.22 and not has:photo");
+    assert_events!(&matching, "0 sota 2018.01.22 10:05:13");
+
+    let matching = read_matching("messages.html", "(author:denko or has:link) and level:0");
+    assert_events!(&matching, "0 denko 2018.01.21 17:02:54", "0 sota 2018.01.22 10:05:13");
+
+    let matching = read_matching("messages_attachments.html", "has:sticker or has:location");
+    assert_events!(&matching, "0 sota 2018.01.21 13:53:59", "0 sota 2018.01.21 13:53:59");
+}
+
+#[test]
+fn it_matches_whole_days() {
+    let matching = read_matching("messages.html", "date:2018.01.22");
+    assert_events!(&matching, "0 sota 2018.01.22 10:03:04", "0 sota 2018.01.22 10:05:13");
+
+    let matching = read_matching("messages.html", "date>2018.01.21 and body:pixiv");
+    assert_events!(&matching, "0 sota 2018.01.22 10:05:13");
+
+    let query = "date<\"2018.01.22 10:05:13\" and not date<2018.01.22";
+    let matching = read_matching("messages.html", query);
+    assert_events!(&matching, "0 sota 2018.01.22 10:03:04");
+}
+
+#[test]
+fn it_matches_forwarded_messages_by_level() {
+    let matching = read_matching("messages_forwarded.html", "level>=2 or name:Denko and level<1");
+    assert_events!(
+        &matching,
+        "0 denko 2019.01.02 07:03:18",
+        "2 sota 2018.01.21 20:48:19",
+        "3 denko 2018.01.21 20:48:07",
+        "3 denko 2018.01.21 20:48:10"
+    );
+}
+
+#[test]
+fn it_reports_query_errors() {
+    let error = |query| Predicate::parse(query).unwrap_err();
+    assert_eq!(error("author:"), QueryError {
+        position: 7,
+        message: "expected a value".to_owned()
+    });
+    assert_eq!(error("(author:sota").message, "expected )");
+    assert_eq!(error("author:sota or").message, "expected a term");
+    assert_eq!(error("author:sota sota").message, "unexpected input");
+    assert_eq!(error("has:gif").message, "unknown attachment kind");
+    assert_eq!(error("author>sota").message, "author only supports : and =");
+    assert_eq!(error("mentions:sota").message, "unknown term mentions");
+    assert_eq!(error("date>=yesterday").position, 6);
+    assert_eq!(error("нет").message, "expected a term");
+}
+
@@ -6,9 +6,11 @@ use test_helper::*;
 fn it_skips_chat_actions() {
     let events = read_events("messages.html");
     assert_events!(
-        &events[..5],
+        &events[..7],
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "Start(0)"
@@ -19,12 +21,15 @@ fn it_skips_chat_actions() {
 fn it_parses_text_messages() {
     let events = read_events("messages.html");
     assert_events!(
-        &events[4..13],
+        &events[6..18],
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 11:05:13\")",
         "BodyPartExtracted(\"Hi Denko\")",
+        "MentionExtracted { id: 2342343, name: \"Denko\" }",
         "BodyPartExtracted(\"\\n\")",
         "BodyPartExtracted(\"\\n\")",
         "BodyPartExtracted(\"I’m drinking \\\"jasmine\\\" tea right now, thinking about what to have for dinner (´･ω･`)\")",
@@ -36,9 +41,11 @@ fn it_parses_text_messages() {
 fn it_parses_emoji() {
     let events = read_events("messages.html");
     assert_events!(
-        &events[12..20],
+        &events[17..27],
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 17:02:54\")",
         "BodyPartExtracted(\"🤔\")",
@@ -55,45 +62,62 @@ fn it_parses_attachments() {
     assert_events!(
         &events,
         "Start(0)",
+        "MessageIdExtracted(0)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "AttachmentExtracted { kind: Video, url: \"http://vk.com/video00000\", vk_obj: \"video_00000\", description: \"Rick Astley - Never Gonna Give You Up (Video) (3:33)\" }",
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
-        "AttachmentExtracted { kind: Doc, url: \"https://vk.com/doc0_0?hash=aaaaa\", vk_obj: \"\", description: \"voice_message.webm\" }",
+        "AttachmentExtracted { kind: AudioMessage, url: \"https://vk.com/doc0_0?hash=aaaaa\", vk_obj: \"\", description: \"voice_message.webm\" }",
         "Start(0)",
+        "MessageIdExtracted(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "AttachmentExtracted { kind: Audio, url: \"https://useraudio\", vk_obj: \"audio2000015592_456243043\", description: \"Johnny Cash - (Ghost) Riders In the Sky (3:46)\" }",
         "Start(0)",
+        "MessageIdExtracted(3)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "AttachmentExtracted { kind: Photo, url: \"https://userapi.com/0.jpg\", vk_obj: \"photo0_0\", description: \"(640x800)\" }",
         "AttachmentExtracted { kind: Photo, url: \"https://userapi.com/1.jpg\", vk_obj: \"photo0_1\", description: \"(515x400)\" }",
         "Start(0)",
+        "MessageIdExtracted(4)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "AttachmentExtracted { kind: Sticker, url: \"https://vk.com/sticker/1-162-256b\", vk_obj: \"\", description: \"Sticker #162\" }",
         "Start(0)",
+        "MessageIdExtracted(5)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "AttachmentExtracted { kind: Wall, url: \"http://vk.com/wall-0_0\", vk_obj: \"wall-0_0\", description: \"\" }",
         "WallPartExtracted(\"Wall post text\")",
+        "WallAttachmentsStart",
         "AttachmentExtracted { kind: Photo, url: \"https://userapi.com/wall0.jpg\", vk_obj: \"photo-0_0\", description: \"(820x1300)\" }",
         "RawAttachmentPartExtracted(\"{\\r\\n   \\\"type\\\": \\\"poll\\\",\\r\\n   \\\"poll\\\": {\\r\\n   \\\"answers\\\": [\\r\\n      {\\\"id\\\": 0, \\\"rate\\\": 146.0, \\\"text\\\": \\\"DA\\\", \\\"votes\\\": 20},\\r\\n      {\\\"id\\\": 1, \\\"rate\\\": 5.0, \\\"text\\\": \\\"NE\\\", \\\"votes\\\": 80}\\r\\n   ]}\\r\\n}\")",
+        "WallAttachmentsEnd",
         "Start(0)",
+        "MessageIdExtracted(6)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 13:53:59\")",
         "BodyPartExtracted(\"wish you were here\")",
-        "AttachmentExtracted { kind: Location, url: \"https://maps.google.ru/maps?q=34.691139132132 135.50335473102\", vk_obj: \"\", description: \"Location: Osaka, Japan\" }"
+        "AttachmentExtracted { kind: Location, url: \"https://maps.google.ru/maps?q=34.691139132132 135.50335473102\", vk_obj: \"\", description: \"Location: Osaka, Japan\" }",
+        "DumpEnd"
     );
 }
 
@@ -101,21 +125,26 @@ fn it_parses_attachments() {
 fn it_parses_attachments_without_body() {
     let events = read_events("messages.html");
     assert_events!(
-        &events[19..25],
+        &events[26..34],
         "Start(0)",
+        "MessageIdExtracted(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:03:04\")",
         "AttachmentExtracted { kind: Photo, url: \"photo\", vk_obj: \"photo420_13\", description: \"(420x6)\" }",
         "Start(0)"
     );
     assert_events!(
-        &events[24..],
+        &events[33..],
         "Start(0)",
+        "MessageIdExtracted(3)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.22 10:05:13\")",
-        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here\\\'s my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")"
+        "BodyPartExtracted(\"W-what do you think? I hope you like it (´･ω･`) here's my pixiv to crash the parser https://t.co/aaaaaa&lt;/a&gt\")",
+        "DumpEnd"
     );
 }
 
@@ -125,23 +154,28 @@ fn it_parses_forwarded_messages_with_arbitrary_nesting() {
     assert_events!(
         &events,
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2019.01.02 07:03:18\")",
         "BodyPartExtracted(\"take it and leave\")",
         "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
         "Start(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2019.01.02 07:02:58\")",
         "BodyPartExtracted(\"pwetty pwease\")",
         "Start(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2019.01.02 07:03:04\")",
-        "BodyPartExtracted(\"pwease don\\\'t ignore me (´･ω･`)\")",
+        "BodyPartExtracted(\"pwease don't ignore me (´･ω･`)\")",
         "Start(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 20:48:19\")",
         "BodyPartExtracted(\"how about now? (´･ω･`)\")",
@@ -149,73 +183,91 @@ fn it_parses_forwarded_messages_with_arbitrary_nesting() {
         "FullNameExtracted(\"Denko\")",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 20:48:07\")",
-        "BodyPartExtracted(\"ugh you just won\\\'t leave me alone will you\")",
+        "BodyPartExtracted(\"ugh you just won't leave me alone will you\")",
         "Start(3)",
         "FullNameExtracted(\"Denko\")",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 20:48:10\")",
-        "BodyPartExtracted(\"I\\\'ll do it\")",
+        "BodyPartExtracted(\"I'll do it\")",
         "Start(1)",
         "FullNameExtracted(\"Denko\")",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2019.01.02 07:03:06\")",
-        "BodyPartExtracted(\"tomorrow maybe\")"
+        "BodyPartExtracted(\"tomorrow maybe\")",
+        "DumpEnd"
     );
 }
 
+#[test]
+fn it_skips_message_ids_of_skipped_messages() {
+    let events = read_events_skipping("messages_attachments.html", |e| {
+        !matches!(e, MessageEvent::Start(_))
+    });
+    let mut expected = vec!["Start(0)"; 7];
+    expected.push("DumpEnd");
+    assert_eq!(events, expected);
+}
+
 #[test]
 fn it_skips_forwarded_messages() {
-    let events = read_events_skipping("messages_forwarded.html", |e| match e {
-        MessageEvent::DateExtracted("2018.01.21 20:48:19") => false,
-        _ => true,
+    let events = read_events_skipping("messages_forwarded.html", |e| {
+        !matches!(e, MessageEvent::DateExtracted("2018.01.21 20:48:19"))
     });
     assert_events!(
         &events,
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2019.01.02 07:03:18\")",
         "BodyPartExtracted(\"take it and leave\")",
         "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
         "Start(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2019.01.02 07:02:58\")",
         "BodyPartExtracted(\"pwetty pwease\")",
         "Start(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2019.01.02 07:03:04\")",
-        "BodyPartExtracted(\"pwease don\\\'t ignore me (´･ω･`)\")",
+        "BodyPartExtracted(\"pwease don't ignore me (´･ω･`)\")",
         "Start(2)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 20:48:19\")",
         "Start(1)",
         "FullNameExtracted(\"Denko\")",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2019.01.02 07:03:06\")",
-        "BodyPartExtracted(\"tomorrow maybe\")"
+        "BodyPartExtracted(\"tomorrow maybe\")",
+        "DumpEnd"
     );
 }
 
 #[test]
 fn it_skips_forwarded_messages_2() {
-    let events = read_events_skipping("messages_forwarded.html", |e| match e {
-        MessageEvent::Start(1) => false,
-        _ => true,
+    let events = read_events_skipping("messages_forwarded.html", |e| {
+        !matches!(e, MessageEvent::Start(1))
     });
     assert_events!(
         &events,
         "Start(0)",
+        "MessageIdExtracted(1)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2019.01.02 07:03:18\")",
         "BodyPartExtracted(\"take it and leave\")",
         "AttachmentExtracted { kind: Photo, url: \"https://sun9-64.userapi.com/c/v/h/m.jpg\", vk_obj: \"photo1\", description: \"(400x400)\" }",
         "Start(1)",
         "Start(1)",
-        "Start(1)"
+        "Start(1)",
+        "DumpEnd"
     );
 }
 
@@ -226,30 +278,39 @@ fn it_parses_forwarded_messages_with_attachments() {
     assert_events!(
         &events,
         "Start(0)",
+        "MessageIdExtracted(240136)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 19:00:55\")",
         "AttachmentExtracted { kind: Photo, url: \"https://photo.jpg\", vk_obj: \"photo1\", description: \"(960x793)\" }",
         "Start(0)",
+        "MessageIdExtracted(240137)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 19:02:09\")",
         "BodyPartExtracted(\"I hope this time is the last time for real\")",
         "Start(1)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 18:59:35\")",
         "BodyPartExtracted(\"thankuwu:3:3:3:3:3\")",
         "Start(2)",
         "FullNameExtracted(\"Denko\")",
+        "AuthorIdExtracted(2)",
         "ShortNameExtracted(\"denko\")",
         "DateExtracted(\"2018.01.21 18:58:09\")",
         "BodyPartExtracted(\" \")",
         "AttachmentExtracted { kind: Photo, url: \"https://photo_old.jpg\", vk_obj: \"photo0\", description: \"(597x475)\" }",
         "Start(0)",
+        "MessageIdExtracted(240158)",
         "FullNameExtracted(\"Sota\")",
+        "AuthorIdExtracted(1)",
         "ShortNameExtracted(\"sota\")",
         "DateExtracted(\"2018.01.21 19:36:18\")",
-        "BodyPartExtracted(\"don\\\'t be a meanie uwu you awe so bwutiful\")"
+        "BodyPartExtracted(\"don't be a meanie uwu you awe so bwutiful\")",
+        "DumpEnd"
     );
 }
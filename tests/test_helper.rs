@@ -1,3 +1,6 @@
+#![allow(dead_code)]
+
+use vkopt_message_parser::filter::{BufferedMessage, Filter, MessageBuffer};
 use vkopt_message_parser::reader::{fold_html, EventResult, MessageEvent};
 
 #[macro_export]
@@ -7,15 +10,43 @@ macro_rules! assert_events {
     };
 }
 
+pub fn fixture_path(fixture: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture)
+}
+
+/// Maps each message of the fixture that passes the filter.
+pub fn read_messages<T, F: FnMut(&BufferedMessage) -> T>(
+    fixture: &str,
+    filter: &Filter,
+    mut f: F,
+) -> Vec<T> {
+    let mut buffer = MessageBuffer::new(filter);
+    fold_html(fixture_path(fixture), Vec::new(), |mut vec, event| {
+        if let Some(message) = buffer.push(event) {
+            vec.push(f(&message));
+        }
+        EventResult::Consumed(vec)
+    })
+    .unwrap()
+}
+
+pub fn read_dates(fixture: &str, filter: &Filter) -> Vec<String> {
+    read_messages(fixture, filter, |message| message.date().unwrap().to_owned())
+}
+
+// Line breaks are replaced with spaces
+pub fn read_bodies(fixture: &str, filter: &Filter) -> Vec<String> {
+    read_messages(fixture, filter, |message| message.body().replace('\n', " "))
+}
+
 pub fn read_events(fixture: &str) -> Vec<String> {
     read_events_skipping(fixture, |_| true)
 }
 
 pub fn read_events_skipping<P: Fn(MessageEvent) -> bool>(fixture: &str, pred: P) -> Vec<String> {
-    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("tests/fixtures")
-        .join(fixture);
-    fold_html(path, Vec::new(), |mut vec, event| {
+    fold_html(fixture_path(fixture), Vec::new(), |mut vec, event| {
         vec.push(format!("{:?}", event));
         if pred(event) {
             EventResult::Consumed(vec)
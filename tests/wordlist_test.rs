@@ -0,0 +1,48 @@
+use vkopt_message_parser::filter::Filter;
+use vkopt_message_parser::wordlist::Wordlist;
+
+mod test_helper;
+use test_helper::*;
+
+#[test]
+fn it_matches_inflected_forms() {
+    let wordlist = Wordlist::load(fixture_path("wordlist.txt")).unwrap();
+    for word in &["дурак", "Дураками", "дураку", "ёлки", "Елкой", "блять", "Jasmine", "dinners"] {
+        assert!(wordlist.matches_word(word), "{} is not matched", word);
+    }
+    for word in &["бесит", "бесишь", "Бесила", "бесят", "дуры", "дурой"] {
+        assert!(wordlist.matches_word(word), "{} is not matched", word);
+    }
+    for word in &["дурацкий", "ёлочка", "дурь", "бл", "jasmines", "dinne", "#"] {
+        assert!(!wordlist.matches_word(word), "{} is matched", word);
+    }
+    assert!(wordlist.matches("Ну ты и ДУРАК!"));
+    assert!(!wordlist.matches("ну ты и дурачок"));
+}
+
+#[test]
+fn it_masks_words() {
+    let wordlist = Wordlist::parse("дурак\nбля*");
+    assert_eq!(wordlist.mask("Сам ты дурак, блин... бляха-муха"), "Сам ты *****, блин... *****-муха");
+    assert_eq!(wordlist.mask("дураки"), "******");
+    assert_eq!(wordlist.mask("всё хорошо"), "всё хорошо");
+}
+
+#[test]
+fn it_drops_or_masks_messages() {
+    let filter = Filter {
+        wordlist: Some(Wordlist::load(fixture_path("wordlist.txt")).unwrap()),
+        ..Default::default()
+    };
+    let bodies = read_bodies("messages.html", &filter);
+    assert_eq!(bodies.len(), 4);
+    assert!(bodies.iter().all(|b| !b.contains("jasmine")));
+
+    let filter = Filter { mask_wordlist: true, ..filter };
+    let bodies = read_bodies("messages.html", &filter);
+    assert_eq!(bodies.len(), 5);
+    assert_eq!(
+        bodies[1],
+        "Hi Denko  I’m drinking \"*******\" tea right now, thinking about what to have for ****** (´･ω･`)"
+    );
+}